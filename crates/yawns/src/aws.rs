@@ -1,6 +1,31 @@
 use crate::prelude::*;
+use aws_smithy_http_client::tls::{self, rustls_provider::CryptoMode, TlsContext, TrustStore};
+use std::path::PathBuf;
 
-pub async fn get_sdk_config_from_global(global: crate::Global) -> Result<aws_config::SdkConfig> {
+#[derive(Debug, clap::Args, Clone)]
+pub struct EndpointOptions {
+    /// Endpoint URL used for every service instead of the AWS one, e.g. to talk to MinIO, Ceph
+    /// RGW or LocalStack. `AWS_ENDPOINT_URL`, `AWS_ENDPOINT_URL_<SERVICE>` and the profile
+    /// `endpoint_url` settings are honored when not given.
+    #[clap(long, env = "YAWNS_ENDPOINT_URL", global = true)]
+    pub endpoint_url: Option<String>,
+    /// Endpoint URL used for S3 only. Takes precedence over `--endpoint-url`.
+    #[clap(long, env = "YAWNS_S3_ENDPOINT_URL", global = true)]
+    pub s3_endpoint_url: Option<String>,
+    /// Endpoint URL used for KMS only. Takes precedence over `--endpoint-url`.
+    #[clap(long, env = "YAWNS_KMS_ENDPOINT_URL", global = true)]
+    pub kms_endpoint_url: Option<String>,
+    /// Address S3 buckets as `endpoint/bucket` instead of `bucket.endpoint`, as most
+    /// S3-compatible stores expect.
+    #[clap(long, env = "YAWNS_FORCE_PATH_STYLE", global = true)]
+    pub force_path_style: bool,
+    /// PEM file with the certificates trusted on top of the system ones, e.g. the CA of an
+    /// on-prem gateway with a self-signed certificate.
+    #[clap(long, env = "AWS_CA_BUNDLE", global = true)]
+    pub ca_bundle: Option<PathBuf>,
+}
+
+pub async fn get_sdk_config_from_global(global: &crate::Global) -> Result<aws_config::SdkConfig> {
     let config_loader = aws_config::from_env();
 
     let config_loader = if let Some(region) = global.region.clone() {
@@ -15,5 +40,69 @@ pub async fn get_sdk_config_from_global(global: crate::Global) -> Result<aws_con
         config_loader
     };
 
+    let config_loader = config_loader.retry_config(global.retry.sdk_retry_config());
+
+    let config_loader = if let Some(endpoint_url) = global.endpoint.endpoint_url.clone() {
+        config_loader.endpoint_url(endpoint_url)
+    } else {
+        config_loader
+    };
+
+    let config_loader = if let Some(ca_bundle) = global.endpoint.ca_bundle.as_ref() {
+        let pem = std::fs::read(ca_bundle)
+            .wrap_err_with(|| f!("Failed to read CA bundle {}", ca_bundle.display()))?;
+        let tls_context = TlsContext::builder()
+            .with_trust_store(TrustStore::default().with_pem_certificate(pem))
+            .build()
+            .wrap_err_with(|| f!("Invalid CA bundle {}", ca_bundle.display()))?;
+
+        config_loader.http_client(
+            aws_smithy_http_client::Builder::new()
+                .tls_provider(tls::Provider::Rustls(CryptoMode::AwsLc))
+                .tls_context(tls_context)
+                .build_https(),
+        )
+    } else {
+        config_loader
+    };
+
     Ok(config_loader.load().await)
 }
+
+/// Builds the S3 client, applying the S3 specific endpoint settings.
+pub fn s3_client(config: &aws_config::SdkConfig, global: &crate::Global) -> aws_sdk_s3::Client {
+    let mut builder = aws_sdk_s3::config::Builder::from(config);
+
+    if let Some(endpoint_url) = global.endpoint.s3_endpoint_url.as_deref() {
+        builder = builder.endpoint_url(endpoint_url);
+    }
+    if global.endpoint.force_path_style {
+        builder = builder.force_path_style(true);
+    }
+
+    aws_sdk_s3::Client::from_conf(builder.build())
+}
+
+/// Copy of `client` that makes a single attempt per request and leaves the retries to the
+/// caller, so that every failed attempt reaches it. Used for the requests retried by
+/// [`crate::retry::RetryOptions::run`], which would otherwise be retried by the SDK on top.
+pub fn single_attempt(client: &aws_sdk_s3::Client) -> aws_sdk_s3::Client {
+    let config = client
+        .config()
+        .to_builder()
+        .retry_config(aws_config::retry::RetryConfig::disabled())
+        .build();
+
+    aws_sdk_s3::Client::from_conf(config)
+}
+
+/// Builds the KMS client, applying the KMS specific endpoint settings.
+pub fn kms_client(config: &aws_config::SdkConfig, global: &crate::Global) -> aws_sdk_kms::Client {
+    let mut builder = aws_sdk_kms::config::Builder::from(config);
+
+    if let Some(endpoint_url) = global.endpoint.kms_endpoint_url.as_deref() {
+        builder = builder.endpoint_url(endpoint_url);
+    }
+
+    aws_sdk_kms::Client::from_conf(builder.build())
+}
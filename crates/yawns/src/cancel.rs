@@ -0,0 +1,103 @@
+use crate::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Whether the run was asked to stop.
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Why the run was asked to stop.
+static REASON: OnceLock<String> = OnceLock::new();
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct CancelOptions {
+    /// Max duration of the whole run of a bulk command (e.g. `30m`, `2h`.) Once it is over, the
+    /// run stops the way it does on Ctrl-C.
+    #[clap(
+        long,
+        env = "YAWNS_TIMEOUT",
+        global = true,
+        value_parser = humantime::parse_duration
+    )]
+    pub timeout: Option<Duration>,
+}
+
+/// Stops the run on the first SIGINT or SIGTERM, or once the timeout is over: no new object
+/// gets processed, and the multipart transfers in flight get aborted, while the single requests
+/// in flight finish. The process exits right away on the second signal.
+///
+/// Only meant for the bulk commands: once installed, the signals no longer kill the process
+/// right away, which would leave the others, and the prompts, impossible to interrupt.
+pub fn install(options: &CancelOptions) -> Result<()> {
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+
+    tokio::spawn(async move {
+        let mut interrupted = false;
+
+        loop {
+            #[cfg(unix)]
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            #[cfg(not(unix))]
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+
+            if interrupted {
+                aeprintln!("Interrupted again, quitting.");
+                std::process::exit(130);
+            }
+            interrupted = true;
+            aeprintln!(
+                "Interrupted, waiting for the requests in flight to finish. Interrupt again to quit right away."
+            );
+            cancel("interrupted");
+        }
+    });
+
+    if let Some(timeout) = options.timeout {
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if !requested() {
+                aeprintln!(
+                    "Timed out after {}, waiting for the requests in flight to finish.",
+                    humantime::format_duration(timeout)
+                );
+                cancel(&f!(
+                    "timed out after {}",
+                    humantime::format_duration(timeout)
+                ));
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn cancel(reason: &str) {
+    _ = REASON.set(reason.to_string());
+    CANCELLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether the run was asked to stop.
+pub fn requested() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+/// Fails once the run was asked to stop, so that the work not started yet gets skipped.
+pub fn check() -> Result<()> {
+    if requested() {
+        let reason = REASON.get().map(String::as_str).unwrap_or("cancelled");
+        Err(Error::Cancelled(f!("Cancelled, {reason}")).into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether an error comes from [`check`].
+pub fn is_cancelled(err: &color_eyre::Report) -> bool {
+    matches!(err.downcast_ref::<Error>(), Some(Error::Cancelled(_)))
+}
@@ -2,4 +2,12 @@
 pub enum Error {
     #[error("Generic {0}")]
     Generic(String),
+    #[error("{0}")]
+    Retryable(String),
+    /// A retryable error caused by the service throttling the requests.
+    #[error("{0}")]
+    Throttled(String),
+    /// The run was asked to stop before the work was done.
+    #[error("{0}")]
+    Cancelled(String),
 }
@@ -1,3 +1,4 @@
+use crate::output::{OutputFormat, Record};
 use crate::prelude::*;
 use futures::future::join_all;
 
@@ -27,38 +28,76 @@ pub struct GetPolicyOptions {
     alias: String,
 }
 
+/// A KMS key and its aliases.
+#[derive(Debug, serde::Serialize)]
+pub struct Key {
+    pub arn: String,
+    pub aliases: Vec<String>,
+}
+
+impl Record for Key {
+    const COLUMNS: &'static [&'static str] = &["Arn", "Id"];
+
+    fn row(&self) -> Vec<String> {
+        vec![self.arn.clone(), self.aliases.join(", ")]
+    }
+}
+
+/// The default policy of a KMS key.
+#[derive(Debug, serde::Serialize)]
+pub struct KeyPolicy {
+    pub key_id: String,
+    pub policy: serde_json::Value,
+}
+
+impl Record for KeyPolicy {
+    const COLUMNS: &'static [&'static str] = &["KeyId", "Policy"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.key_id.clone(),
+            serde_json::to_string_pretty(&self.policy).unwrap_or_default(),
+        ]
+    }
+}
+
 pub async fn run(app: App, global: crate::Global) -> Result<()> {
     if global.verbose {
-        aprintln!("KMS Client Version: {}", aws_sdk_kms::meta::PKG_VERSION);
-        aprintln!(
+        aeprintln!("KMS Client Version: {}", aws_sdk_kms::meta::PKG_VERSION);
+        aeprintln!(
             "AWS Region        : {}",
             global
                 .region
                 .as_ref()
                 .ok_or_else(|| eyre!("AWS_REGION not defined"))?
         );
-        aprintln!();
+        aeprintln!();
     }
 
-    let config = crate::aws::get_sdk_config_from_global(global).await?;
+    let config = crate::aws::get_sdk_config_from_global(&global).await?;
 
-    let client = aws_sdk_kms::Client::new(&config);
+    let client = crate::aws::kms_client(&config, &global);
 
     match app.command {
-        Commands::ListKeys => list_keys(client).await,
-        Commands::GetPolicy(options) => get_policy(client, options).await,
+        Commands::ListKeys => global.output.list(&list_keys(client).await?),
+        Commands::GetPolicy(options) => match get_policy(client, options).await? {
+            // The policy is already a JSON document, so it's printed as is.
+            Some(policy) if global.output == OutputFormat::Table => {
+                aprintln!("{}", serde_json::to_string_pretty(&policy.policy)?);
+                Ok(())
+            }
+            Some(policy) => global.output.one(&policy),
+            None => Ok(()),
+        },
     }
 }
 
-pub async fn list_keys(client: aws_sdk_kms::Client) -> Result<()> {
+pub async fn list_keys(client: aws_sdk_kms::Client) -> Result<Vec<Key>> {
     let resp = client.list_keys().send().await?;
 
     log::info!("Getting the list of KMS keys");
     let keys = resp.keys.unwrap_or_default();
 
-    let mut table = new_table();
-    table.set_titles(prettytable::row!["Arn", "Id"]);
-
     let alias_futures = keys.into_iter().map(|key| {
         let client = client.clone();
 
@@ -68,40 +107,52 @@ pub async fn list_keys(client: aws_sdk_kms::Client) -> Result<()> {
 
             let resp = client.list_aliases().key_id(key_id).send().await?;
             let aliases = resp.aliases.unwrap_or_default();
-            let alias_names = aliases
-                .iter()
-                .map(|alias| alias.alias_name.as_deref().unwrap_or_default())
-                .collect::<Vec<&str>>()
-                .join(", ");
-            Ok((key.key_arn.unwrap_or_default(), alias_names)) as Result<(String, String)>
+            let aliases = aliases
+                .into_iter()
+                .filter_map(|alias| alias.alias_name)
+                .collect();
+            Ok(Key {
+                arn: key.key_arn.unwrap_or_default(),
+                aliases,
+            }) as Result<Key>
         }
     });
 
     let results = join_all(alias_futures).await;
 
-    for (arn, alias_names) in results.into_iter().flatten() {
-        table.add_row(prettytable::row![arn, alias_names]);
-    }
-
-    aprintln!("{}", table.to_string());
-
-    Ok(())
+    Ok(results.into_iter().flatten().collect())
 }
 
-pub async fn get_policy(client: aws_sdk_kms::Client, options: GetPolicyOptions) -> Result<()> {
-    let resp = client.describe_key().key_id(options.alias).send().await?;
-
-    if let Some(metadata) = resp.key_metadata {
-        let resp = client
-            .get_key_policy()
-            .key_id(metadata.key_id)
-            .policy_name("default")
-            .send()
-            .await?;
-        if let Some(policy) = resp.policy {
-            aprintln!("{}", policy)
-        }
+pub async fn get_policy(
+    client: aws_sdk_kms::Client,
+    options: GetPolicyOptions,
+) -> Result<Option<KeyPolicy>> {
+    let Some(metadata) = describe_key(&client, &options.alias).await? else {
+        return Ok(None);
+    };
+
+    let resp = client
+        .get_key_policy()
+        .key_id(&metadata.key_id)
+        .policy_name("default")
+        .send()
+        .await?;
+
+    match resp.policy {
+        Some(policy) => Ok(Some(KeyPolicy {
+            key_id: metadata.key_id,
+            policy: serde_json::from_str(&policy)?,
+        })),
+        None => Ok(None),
     }
+}
+
+/// Gets the details of a key given its id, ARN, alias name or alias ARN.
+pub async fn describe_key(
+    client: &aws_sdk_kms::Client,
+    key_id: &str,
+) -> Result<Option<aws_sdk_kms::types::KeyMetadata>> {
+    let resp = client.describe_key().key_id(key_id).send().await?;
 
-    Ok(())
+    Ok(resp.key_metadata)
 }
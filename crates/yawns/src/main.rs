@@ -4,9 +4,12 @@ use crate::prelude::*;
 use clap::Parser;
 
 mod aws;
+mod cancel;
 mod error;
 mod kms;
+mod output;
 mod prelude;
+mod retry;
 mod s3;
 
 #[derive(Debug, clap::Parser)]
@@ -36,6 +39,19 @@ pub struct Global {
     /// Whether to display additional information.
     #[clap(long, env = "YAWNS_VERBOSE", global = true, default_value = "false")]
     verbose: bool,
+
+    /// Format of the results printed to stdout. Progress and diagnostics always go to stderr.
+    #[clap(long, env = "YAWNS_OUTPUT", global = true, value_enum, default_value_t)]
+    output: crate::output::OutputFormat,
+
+    #[clap(flatten)]
+    retry: crate::retry::RetryOptions,
+
+    #[clap(flatten)]
+    cancel: crate::cancel::CancelOptions,
+
+    #[clap(flatten)]
+    endpoint: crate::aws::EndpointOptions,
 }
 
 #[derive(Debug, clap::Parser)]
@@ -44,7 +60,7 @@ pub enum SubCommands {
     KMS(crate::kms::App),
 
     /// AWS S3
-    S3(crate::s3::App),
+    S3(Box<crate::s3::App>),
 }
 
 #[tokio::main]
@@ -56,7 +72,7 @@ async fn main() -> Result<()> {
 
     match app.command {
         SubCommands::KMS(sub_app) => crate::kms::run(sub_app, app.global).await,
-        SubCommands::S3(sub_app) => crate::s3::run(sub_app, app.global).await,
+        SubCommands::S3(sub_app) => crate::s3::run(*sub_app, app.global).await,
     }
     .map_err(|err: color_eyre::eyre::Report| eyre!(err))
 }
@@ -0,0 +1,81 @@
+use crate::prelude::*;
+
+/// How the results of a command are printed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable table.
+    #[default]
+    Table,
+    /// A single JSON document.
+    Json,
+    /// One JSON object per line.
+    Jsonl,
+    /// A single YAML document.
+    Yaml,
+    /// Comma separated values, with a header row.
+    Csv,
+}
+
+/// A result of a command that can be printed in every output format.
+pub trait Record: serde::Serialize {
+    /// Titles of the table and CSV columns.
+    const COLUMNS: &'static [&'static str];
+
+    /// Values of the table and CSV columns, in the same order as `COLUMNS`.
+    fn row(&self) -> Vec<String>;
+}
+
+impl OutputFormat {
+    /// Prints a list of records to stdout.
+    pub fn list<T: Record>(&self, records: &[T]) -> Result<()> {
+        match self {
+            OutputFormat::Json => aprintln!("{}", serde_json::to_string_pretty(records)?),
+            OutputFormat::Yaml => aprintln!("{}", serde_yaml::to_string(records)?.trim_end()),
+            _ => self.rows(records)?,
+        }
+
+        Ok(())
+    }
+
+    /// Prints a single record to stdout. JSON and YAML print it as an object instead of as a
+    /// list with a single element.
+    pub fn one<T: Record>(&self, record: &T) -> Result<()> {
+        match self {
+            OutputFormat::Json => aprintln!("{}", serde_json::to_string_pretty(record)?),
+            OutputFormat::Yaml => aprintln!("{}", serde_yaml::to_string(record)?.trim_end()),
+            _ => self.rows(std::slice::from_ref(record))?,
+        }
+
+        Ok(())
+    }
+
+    /// Prints the formats that have one line per record.
+    fn rows<T: Record>(&self, records: &[T]) -> Result<()> {
+        match self {
+            OutputFormat::Table => {
+                let mut table = new_table();
+                table.set_titles(T::COLUMNS.iter().collect());
+                for record in records {
+                    table.add_row(record.row().iter().collect());
+                }
+                aprintln!("{}", table.to_string());
+            }
+            OutputFormat::Jsonl => {
+                for record in records {
+                    aprintln!("{}", serde_json::to_string(record)?);
+                }
+            }
+            OutputFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                writer.write_record(T::COLUMNS)?;
+                for record in records {
+                    writer.write_record(record.row())?;
+                }
+                aprintln!("{}", String::from_utf8(writer.into_inner()?)?.trim_end());
+            }
+            OutputFormat::Json | OutputFormat::Yaml => unreachable!("not a row based format"),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::prelude::*;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use std::future::Future;
+use std::time::Duration;
+
+/// Upper bound for the delay between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(20);
+
+/// Error codes returned by AWS services when they throttle the requests.
+const THROTTLING_CODES: &[&str] = &[
+    "SlowDown",
+    "Throttling",
+    "ThrottlingException",
+    "ThrottledException",
+    "RequestThrottled",
+    "RequestThrottledException",
+    "TooManyRequestsException",
+    "RequestLimitExceeded",
+];
+
+/// Other error codes returned by AWS services that are worth retrying.
+const RETRYABLE_CODES: &[&str] = &[
+    "RequestTimeout",
+    "RequestTimeoutException",
+    "InternalError",
+    "ServiceUnavailable",
+];
+
+/// HTTP status codes that signal throttling.
+const THROTTLING_STATUS_CODES: &[u16] = &[429, 503];
+
+/// Other HTTP status codes that signal a transient failure.
+const RETRYABLE_STATUS_CODES: &[u16] = &[500, 502, 504];
+
+/// Retry strategy used by the AWS SDK clients.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RetryMode {
+    /// Exponential backoff with jitter.
+    #[default]
+    Standard,
+    /// Like `standard`, but also rate limits the client when it gets throttled.
+    Adaptive,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct RetryOptions {
+    /// Max number of times a failed request, or a failed object of a bulk operation, is retried.
+    #[clap(long, env = "YAWNS_MAX_RETRIES", global = true, default_value = "3")]
+    pub max_retries: u32,
+    /// Base delay of the exponential backoff between retries (e.g. `100ms`, `2s`.)
+    #[clap(
+        long,
+        env = "YAWNS_RETRY_BASE_DELAY",
+        global = true,
+        default_value = "100ms",
+        value_parser = humantime::parse_duration
+    )]
+    pub retry_base_delay: Duration,
+    /// Retry strategy used by the AWS clients, for the requests that aren't part of an object of
+    /// a bulk operation, such as listings. The objects are retried with plain exponential backoff.
+    #[clap(
+        long,
+        env = "YAWNS_RETRY_MODE",
+        global = true,
+        value_enum,
+        default_value_t
+    )]
+    pub retry_mode: RetryMode,
+}
+
+impl RetryOptions {
+    /// Builds the retry configuration handed to the AWS SDK.
+    pub fn sdk_retry_config(&self) -> aws_config::retry::RetryConfig {
+        let config = match self.retry_mode {
+            RetryMode::Standard => aws_config::retry::RetryConfig::standard(),
+            RetryMode::Adaptive => aws_config::retry::RetryConfig::adaptive(),
+        };
+
+        config
+            .with_max_attempts(self.max_retries + 1)
+            .with_initial_backoff(self.retry_base_delay)
+            .with_max_backoff(MAX_BACKOFF)
+    }
+
+    /// Runs `operation` until it succeeds, fails with a non-retryable error, or runs out of
+    /// retries. Returns the last result together with the number of attempts made.
+    ///
+    /// Once the run is cancelled, no new attempt is made and the cancellation is returned. The
+    /// requests made by `operation` should go through [`crate::aws::single_attempt`] clients,
+    /// so that an attempt isn't retried by the SDK too.
+    pub async fn run<T, F, Fut>(&self, mut operation: F) -> (Result<T>, u32)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            if let Err(err) = crate::cancel::check() {
+                return (Err(err), attempt);
+            }
+            attempt += 1;
+
+            match operation().await {
+                Ok(value) => return (Ok(value), attempt),
+                Err(err) if attempt <= self.max_retries && is_retryable(&err) => {
+                    let delay = self.backoff(attempt);
+                    log::warn!(
+                        "Attempt {} failed, retrying in {:.2?}: {}",
+                        attempt,
+                        delay,
+                        err
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(err) => return (Err(err), attempt),
+            }
+        }
+    }
+
+    /// Exponential backoff with full jitter for the given (1-based) attempt.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .retry_base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(MAX_BACKOFF);
+
+        ceiling.mul_f64(fastrand::f64())
+    }
+}
+
+/// Whether an error produced by [`classify`] is worth retrying.
+pub fn is_retryable(err: &color_eyre::Report) -> bool {
+    matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::Retryable(_) | Error::Throttled(_))
+    )
+}
+
+/// Whether an error produced by [`classify`] comes from the service throttling the requests.
+pub fn is_throttled(err: &color_eyre::Report) -> bool {
+    matches!(err.downcast_ref::<Error>(), Some(Error::Throttled(_)))
+}
+
+/// Turns an AWS SDK error into a report, flagging transient failures (throttling, 5xx,
+/// timeouts and connection errors) as retryable.
+pub fn classify<E>(context: &str, err: SdkError<E, HttpResponse>) -> color_eyre::Report
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+{
+    let matches = |codes: &[&str], status_codes: &[u16]| {
+        err.code().is_some_and(|code| codes.contains(&code))
+            || err
+                .raw_response()
+                .is_some_and(|raw| status_codes.contains(&raw.status().as_u16()))
+    };
+    let (throttled, retryable) = match &err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            (false, true)
+        }
+        SdkError::ServiceError(_) => (
+            matches(THROTTLING_CODES, THROTTLING_STATUS_CODES),
+            matches(RETRYABLE_CODES, RETRYABLE_STATUS_CODES),
+        ),
+        _ => (false, false),
+    };
+
+    let message = f!("{}: {}", context, DisplayErrorContext(&err));
+
+    if throttled {
+        Error::Throttled(message).into()
+    } else if retryable {
+        Error::Retryable(message).into()
+    } else {
+        eyre!(message)
+    }
+}
@@ -1,15 +1,44 @@
+use crate::output::Record;
 use crate::prelude::*;
-use aws_smithy_types::byte_stream::ByteStream;
+use crate::retry::RetryOptions;
 use futures::future::join_all;
+use futures::{StreamExt, TryStreamExt};
 use std::path::PathBuf;
-use std::str::Bytes;
-use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
 use tokio::sync::Semaphore;
-use tokio::time::{sleep, Duration};
+
+mod checksum;
+mod concurrency;
+mod copy;
+mod delete;
+mod diff;
+mod download;
+mod du;
+mod failed;
+mod find;
+mod journal;
+mod listing;
+mod manifest;
+mod multipart;
+mod mv;
+mod progress;
+mod recorder;
+mod report;
+mod sync;
+mod throttle;
+mod upload;
+mod write;
+
+use concurrency::Concurrency;
+use copy::{copy_object, ObjectCopy};
+use download::{download_object, ObjectDownload};
+use listing::ListingOptions;
+use multipart::MultipartOptions;
+use progress::{Progress, Summary};
+use recorder::Recorder;
+use report::{Item, Report};
+use upload::{upload_object, ObjectUpload};
 
 #[derive(Debug, clap::Parser)]
 #[command(name = "s3")]
@@ -32,24 +61,125 @@ pub enum Commands {
     /// Copies a list of objects between buckets.
     ///
     /// The list of files to copy can be given as a CSV file with at least three columns:
-    /// file, source_prefix, destination_prefix, and optionally, metadata. Each column
-    /// should be separated by a comma. Metadata key value pairs are defined as `key=value`
-    /// strings separated by a space.
+    /// file, source_prefix, destination_prefix, and optionally, metadata, storage_class,
+    /// content_type, cache_control, tags, acl, sse, sse_kms_key_id, bucket_key_enabled and
+    /// content_encoding, which override the command-level settings. Fields containing the delimiter must be quoted.
+    /// Metadata and tag key value pairs are defined as `key=value` strings separated by a space.
+    /// With `--header`, columns are matched by name instead.
+    ///
+    /// With `--manifest-format jsonl`, each line is a JSON object instead, where `src` is the
+    /// source key and `dst` the destination key, which defaults to `src`.
     #[clap(name = "copy-list")]
     CopyList(CopyListOptions),
 
+    /// Moves an object between buckets.
+    ///
+    /// The object is copied, and the source is only deleted once the copy is verified to match
+    /// it.
+    #[clap(name = "move")]
+    Move(MoveOptions),
+
+    /// Moves a list of objects between buckets.
+    ///
+    /// Takes the same list of files as `copy-list`. Each source is only deleted once its copy
+    /// is verified to match it, and how it was verified is written to the journal.
+    #[clap(name = "move-list")]
+    MoveList(MoveListOptions),
+
     /// Counts the number of objects in a bucket with a given prefix.
     #[clap(name = "count-files")]
     CountFiles(CountFilesOptions),
 
+    /// Shows the object count and total size of a bucket, broken down by prefix.
+    #[clap(name = "du")]
+    Du(DuOptions),
+
+    /// Finds the objects of a bucket that match every given filter.
+    ///
+    /// The objects are printed as a `copy-list` manifest, in the format given by the manifest
+    /// options, so that both commands can be piped together. Every key is split into its
+    /// "directory" part of `--prefix`, used as the source prefix, and the rest of the key.
+    /// The global `--output` flag doesn't apply.
+    #[clap(name = "find")]
+    Find(FindOptions),
+
     /// Uploads a list of local objects to a remote Bucket.
     ///
-    /// The list of files to copy can be given as a CSV file with at least three columns:
-    /// file, source_prefix, destination_prefix, and optionally, metadata. Each column
-    /// should be separated by a comma. Metadata key value pairs are defined as `key=value`
-    /// strings separated by a space.
+    /// The list of files to upload can be given as a CSV file with at least one column:
+    /// local_path, and optionally, destination_prefix, metadata, storage_class, content_type,
+    /// cache_control, tags, acl, sse, sse_kms_key_id, bucket_key_enabled and content_encoding,
+    /// which override the command-level settings. Files without a content_type get one detected
+    /// as `--detect-content-type` says. Fields containing the delimiter must be quoted. Metadata and tag
+    /// key value pairs are defined as `key=value` strings separated by a space. With `--header`,
+    /// columns are matched by name instead.
+    ///
+    /// With `--manifest-format jsonl`, each line is a JSON object instead, where `src` is the
+    /// local path and `dst` the destination key, which defaults to the file name under the
+    /// destination prefix.
     #[clap(name = "upload-list")]
     UploadList(UploadListOptions),
+
+    /// Downloads a list of remote objects to the local disk.
+    ///
+    /// The list of objects to download can be given as a CSV file with at least two columns:
+    /// key, local_destination, and optionally, version_id. Fields containing the delimiter must
+    /// be quoted. With `--header`, columns are matched by name instead.
+    ///
+    /// With `--manifest-format jsonl`, each line is a JSON object instead, where `src` is the
+    /// key, `dst` the local destination, and `version_id` the optional version to download.
+    #[clap(name = "download-list")]
+    DownloadList(DownloadListOptions),
+
+    /// Deletes a list of objects from a bucket.
+    ///
+    /// The list of objects to delete can be given as a CSV file with at least one column:
+    /// key, and optionally, version_id. Fields containing the delimiter must be quoted. With
+    /// `--header`, columns are matched by name instead.
+    ///
+    /// With `--manifest-format jsonl`, each line is a JSON object instead, where `src` is the
+    /// key, and `version_id` the optional version to delete.
+    ///
+    /// Objects are deleted in batches of up to 1000 keys.
+    #[clap(name = "delete-list")]
+    DeleteList(DeleteListOptions),
+
+    /// Deletes every object under a prefix.
+    ///
+    /// Asks for confirmation unless `--yes` is given. With `--all-versions`, every version and
+    /// delete marker is deleted too, which is what empties a prefix of a versioned bucket.
+    #[clap(name = "delete-prefix")]
+    DeletePrefix(DeletePrefixOptions),
+
+    /// Compares two S3 prefixes and lists the keys that don't match.
+    ///
+    /// Both sides are given as `s3://bucket/prefix`. Keys are reported when they only exist on
+    /// one side, or when they differ by any of the compared criteria. The command fails when
+    /// any difference is found.
+    #[clap(name = "diff")]
+    Diff(DiffOptions),
+
+    /// Makes a destination look like a source, transferring only what differs.
+    ///
+    /// Either side can be a local directory or an S3 prefix in the form
+    /// `s3://bucket/prefix`, but at least one of them has to be an S3 prefix.
+    #[clap(name = "sync")]
+    Sync(SyncOptions),
+}
+
+impl Commands {
+    /// Whether the command works through a list of objects, and stops gracefully on Ctrl-C or
+    /// once `--timeout` is over. The others keep the default handling of Ctrl-C.
+    fn is_bulk(&self) -> bool {
+        matches!(
+            self,
+            Commands::CopyList(_)
+                | Commands::MoveList(_)
+                | Commands::UploadList(_)
+                | Commands::DownloadList(_)
+                | Commands::DeleteList(_)
+                | Commands::Sync(_)
+        )
+    }
 }
 
 #[derive(Debug, clap::Args, serde::Serialize, serde::Deserialize, Clone)]
@@ -66,6 +196,10 @@ pub struct CopyOptions {
     /// AWS S3 Destination Object.
     #[clap(env = "AWS_S3_DST_OBJECT")]
     dst: String,
+    #[clap(flatten)]
+    multipart: MultipartOptions,
+    #[clap(flatten)]
+    write: write::WriteOptions,
 }
 
 #[derive(Debug, clap::Args, Clone)]
@@ -79,18 +213,59 @@ pub struct CopyListOptions {
     /// AWS S3 Source Object list read from file or Stdin (default.)
     #[clap(env = "AWS_S3_SRC_OBJECT_LIST", default_value = "-")]
     src: clap_stdin::FileOrStdin,
-    /// AWS S3 Source Object prefix.
+    /// AWS S3 Source Object prefix, prepended to every source key.
     #[clap(long, env = "AWS_S3_SRC_OBJECT_PREFIX")]
     source_prefix: Option<String>,
-    /// AWS S3 Source Object prefix.
+    /// AWS S3 Destination Object prefix, prepended to every destination key.
     #[clap(long, env = "AWS_S3_DST_OBJECT_PREFIX")]
     destination_prefix: Option<String>,
     /// Max concurrent copy threads to control the copy rate.
     #[clap(long, env = "AWS_S3_MAX_CONCURRENT", default_value = "10")]
     max_concurrent: usize,
-    /// Metadata to add to the copied object in the form of KEY=VALUE pairs.
+    /// Start with a few concurrent copys and adapt their number to how S3 copes with the load,
+    /// backing off when it throttles the requests, up to `--max-concurrent`.
+    #[clap(long, env = "AWS_S3_ADAPTIVE_CONCURRENCY")]
+    adaptive_concurrency: bool,
+    /// Metadata to add to the copied object in the form of KEY=VALUE pairs. The metadata of
+    /// the source object is preserved when no metadata is given.
     #[clap(short, long, value_parser = parse_key_val::<String, String>, number_of_values = 1)]
     metadata: Option<Vec<(String, String)>>,
+    #[clap(flatten)]
+    multipart: MultipartOptions,
+    #[clap(flatten)]
+    write: write::WriteOptions,
+    /// Progress journal file. Lines completed in a previous run with the same journal are
+    /// skipped, while failed or pending ones are retried.
+    #[clap(long, env = "AWS_S3_JOURNAL")]
+    journal: Option<PathBuf>,
+    /// File where failed lines are written in their original format, followed by an extra
+    /// column with the error reason, so they can be fed back into this command.
+    #[clap(long, env = "AWS_S3_FAILED_OUTPUT")]
+    failed_output: Option<PathBuf>,
+    /// File where a JSON report of the run is written once it is over, even when it fails, with
+    /// its totals and the outcome of every line.
+    #[clap(long, env = "AWS_S3_REPORT")]
+    report: Option<PathBuf>,
+    #[clap(flatten)]
+    manifest: manifest::ManifestOptions,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct MoveOptions {
+    #[clap(flatten)]
+    copy: CopyOptions,
+    /// How the copy is checked before the source gets deleted.
+    #[clap(long, value_enum, default_value_t)]
+    verify: mv::VerifyMode,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct MoveListOptions {
+    #[clap(flatten)]
+    copy: CopyListOptions,
+    /// How each copy is checked before its source gets deleted.
+    #[clap(long, value_enum, default_value_t)]
+    verify: mv::VerifyMode,
 }
 
 #[derive(Debug, clap::Args, Clone)]
@@ -109,6 +284,190 @@ pub struct UploadListOptions {
     /// Max concurrent upload threads to control the upload rate.
     #[clap(long, env = "AWS_S3_MAX_CONCURRENT", default_value = "10")]
     max_concurrent: usize,
+    /// Start with a few concurrent uploads and adapt their number to how S3 copes with the load,
+    /// backing off when it throttles the requests, up to `--max-concurrent`.
+    #[clap(long, env = "AWS_S3_ADAPTIVE_CONCURRENCY")]
+    adaptive_concurrency: bool,
+    /// Progress journal file. Lines completed in a previous run with the same journal are
+    /// skipped, while failed or pending ones are retried.
+    #[clap(long, env = "AWS_S3_JOURNAL")]
+    journal: Option<PathBuf>,
+    /// File where failed lines are written in their original format, followed by an extra
+    /// column with the error reason, so they can be fed back into this command.
+    #[clap(long, env = "AWS_S3_FAILED_OUTPUT")]
+    failed_output: Option<PathBuf>,
+    /// File where a JSON report of the run is written once it is over, even when it fails, with
+    /// its totals and the outcome of every line.
+    #[clap(long, env = "AWS_S3_REPORT")]
+    report: Option<PathBuf>,
+    #[clap(flatten)]
+    manifest: manifest::ManifestOptions,
+    /// Files larger than this size are uploaded with a multipart upload (e.g. `64MiB`.)
+    #[clap(long, env = "AWS_S3_MULTIPART_THRESHOLD", default_value = "64MiB", value_parser = multipart::parse_byte_size)]
+    multipart_threshold: u64,
+    #[clap(flatten)]
+    multipart: MultipartOptions,
+    #[clap(flatten)]
+    write: write::WriteOptions,
+    /// How the Content-Type of the files without one is detected.
+    #[clap(long, env = "AWS_S3_DETECT_CONTENT_TYPE", value_enum, default_value_t)]
+    detect_content_type: upload::ContentTypeDetection,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct DownloadListOptions {
+    /// List of objects to download and their local destination read from file or Stdin (default.)
+    /// Each line should be in the format: key,local_destination[,version_id]
+    /// When the local destination ends with a `/`, or is an existing directory, the object is
+    /// saved inside it using the last segment of its key as the file name.
+    #[clap(env = "AWS_S3_SRC_OBJECT_LIST", default_value = "-")]
+    src: clap_stdin::FileOrStdin,
+    /// AWS S3 Source Bucket.
+    #[clap(long, env = "AWS_S3_SRC_BUCKET")]
+    source_bucket: String,
+    /// AWS S3 Source Object prefix, prepended to every key.
+    #[clap(long, env = "AWS_S3_SRC_OBJECT_PREFIX")]
+    source_prefix: Option<String>,
+    /// Max concurrent download threads to control the download rate.
+    #[clap(long, env = "AWS_S3_MAX_CONCURRENT", default_value = "10")]
+    max_concurrent: usize,
+    /// Progress journal file. Lines completed in a previous run with the same journal are
+    /// skipped, while failed or pending ones are retried.
+    #[clap(long, env = "AWS_S3_JOURNAL")]
+    journal: Option<PathBuf>,
+    /// File where failed lines are written in their original format, followed by an extra
+    /// column with the error reason, so they can be fed back into this command.
+    #[clap(long, env = "AWS_S3_FAILED_OUTPUT")]
+    failed_output: Option<PathBuf>,
+    #[clap(flatten)]
+    manifest: manifest::ManifestOptions,
+    /// Objects larger than this size are downloaded with parallel ranged requests (e.g. `64MiB`.)
+    #[clap(long, env = "AWS_S3_MULTIPART_THRESHOLD", default_value = "64MiB", value_parser = multipart::parse_byte_size)]
+    multipart_threshold: u64,
+    #[clap(flatten)]
+    multipart: MultipartOptions,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct DeleteListOptions {
+    /// List of objects to delete read from file or Stdin (default.)
+    /// Each line should be in the format: key[,version_id]
+    #[clap(env = "AWS_S3_SRC_OBJECT_LIST", default_value = "-")]
+    src: clap_stdin::FileOrStdin,
+    /// AWS S3 Bucket.
+    #[clap(long, env = "AWS_S3_BUCKET")]
+    bucket: String,
+    /// AWS S3 Object prefix, prepended to every key.
+    #[clap(long, env = "AWS_S3_OBJECT_PREFIX")]
+    prefix: Option<String>,
+    /// Print the objects that would be deleted without deleting anything.
+    #[clap(long)]
+    dry_run: bool,
+    /// Max concurrent batch deletions to control the delete rate.
+    #[clap(long, env = "AWS_S3_MAX_CONCURRENT", default_value = "10")]
+    max_concurrent: usize,
+    /// Progress journal file. Lines completed in a previous run with the same journal are
+    /// skipped, while failed or pending ones are retried.
+    #[clap(long, env = "AWS_S3_JOURNAL")]
+    journal: Option<PathBuf>,
+    /// File where failed lines are written in their original format, followed by an extra
+    /// column with the error reason, so they can be fed back into this command.
+    #[clap(long, env = "AWS_S3_FAILED_OUTPUT")]
+    failed_output: Option<PathBuf>,
+    #[clap(flatten)]
+    manifest: manifest::ManifestOptions,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct DeletePrefixOptions {
+    /// AWS S3 Bucket.
+    #[clap(long, env = "AWS_S3_BUCKET")]
+    bucket: String,
+    /// AWS S3 Object prefix to delete. Every object of the bucket is deleted when not given.
+    #[clap(long, env = "AWS_S3_OBJECT_PREFIX")]
+    prefix: Option<String>,
+    /// Delete every version and delete marker, instead of only adding delete markers on
+    /// versioned buckets.
+    #[clap(long)]
+    all_versions: bool,
+    /// Delete without asking for confirmation.
+    #[clap(long, short)]
+    yes: bool,
+    /// Print the objects that would be deleted without deleting anything.
+    #[clap(long)]
+    dry_run: bool,
+    /// Max concurrent batch deletions to control the delete rate.
+    #[clap(long, env = "AWS_S3_MAX_CONCURRENT", default_value = "10")]
+    max_concurrent: usize,
+    #[clap(flatten)]
+    listing: ListingOptions,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct DiffOptions {
+    /// `s3://bucket/prefix` to compare from.
+    source: sync::Location,
+    /// `s3://bucket/prefix` to compare to.
+    destination: sync::Location,
+    /// What to compare for the keys found on both sides.
+    #[clap(long, value_enum, value_delimiter = ',', default_value = "size,etag")]
+    compare: Vec<diff::Criterion>,
+    /// Only compare the keys that match this glob pattern. Can be given multiple times.
+    #[clap(long)]
+    include: Vec<String>,
+    /// Skip the keys that match this glob pattern. Can be given multiple times.
+    #[clap(long)]
+    exclude: Vec<String>,
+    /// File where the keys missing from, or different in, the destination are written as a
+    /// `copy-list` manifest, in the format given by the manifest options.
+    #[clap(long)]
+    manifest_output: Option<PathBuf>,
+    #[clap(flatten)]
+    manifest: manifest::ManifestOptions,
+    /// Max concurrent requests made to compare checksums or metadata.
+    #[clap(long, env = "AWS_S3_MAX_CONCURRENT", default_value = "10")]
+    max_concurrent: usize,
+    #[clap(flatten)]
+    listing: ListingOptions,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct SyncOptions {
+    /// Local directory or `s3://bucket/prefix` to sync from.
+    source: sync::Location,
+    /// Local directory or `s3://bucket/prefix` to sync to.
+    destination: sync::Location,
+    /// Delete the files in the destination that don't exist in the source.
+    #[clap(long)]
+    delete: bool,
+    /// Only sync the keys that match this glob pattern. Can be given multiple times.
+    #[clap(long)]
+    include: Vec<String>,
+    /// Skip the keys that match this glob pattern. Can be given multiple times.
+    #[clap(long)]
+    exclude: Vec<String>,
+    /// How to decide whether a file present on both sides has to be transferred.
+    #[clap(long, value_enum, default_value_t)]
+    compare: sync::CompareMode,
+    /// Print the plan without transferring or deleting anything.
+    #[clap(long)]
+    dry_run: bool,
+    /// Max concurrent transfer threads to control the transfer rate.
+    #[clap(long, env = "AWS_S3_MAX_CONCURRENT", default_value = "10")]
+    max_concurrent: usize,
+    /// Files larger than this size are transferred with multipart or ranged requests (e.g. `64MiB`.)
+    #[clap(long, env = "AWS_S3_MULTIPART_THRESHOLD", default_value = "64MiB", value_parser = multipart::parse_byte_size)]
+    multipart_threshold: u64,
+    #[clap(flatten)]
+    multipart: MultipartOptions,
+    /// Settings of the objects written to S3.
+    #[clap(flatten)]
+    write: write::WriteOptions,
+    /// How the Content-Type of the uploaded files is detected, unless `--content-type` is given.
+    #[clap(long, env = "AWS_S3_DETECT_CONTENT_TYPE", value_enum, default_value_t)]
+    detect_content_type: upload::ContentTypeDetection,
+    #[clap(flatten)]
+    listing: ListingOptions,
 }
 
 #[derive(Debug, clap::Args, Clone)]
@@ -119,6 +478,52 @@ pub struct CountFilesOptions {
     /// AWS S3 Object prefix to count.
     #[clap(long, env = "AWS_S3_OBJECT_PREFIX")]
     prefix: Option<String>,
+    #[clap(flatten)]
+    listing: ListingOptions,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct DuOptions {
+    /// AWS S3 Bucket.
+    #[clap(long, env = "AWS_S3_BUCKET")]
+    bucket: String,
+    /// AWS S3 Object prefix to measure.
+    #[clap(long, env = "AWS_S3_OBJECT_PREFIX")]
+    prefix: Option<String>,
+    /// Number of `/` separated levels below the prefix to group the results by. `0` gives a
+    /// single total.
+    #[clap(long, default_value = "1")]
+    depth: usize,
+    /// Break every prefix down by storage class.
+    #[clap(long)]
+    by_storage_class: bool,
+    /// How to sort the results.
+    #[clap(long, value_enum, default_value_t)]
+    sort: du::SortBy,
+    #[clap(flatten)]
+    listing: ListingOptions,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct FindOptions {
+    /// AWS S3 Bucket.
+    #[clap(long, env = "AWS_S3_BUCKET")]
+    bucket: String,
+    /// AWS S3 Object prefix to search under.
+    #[clap(long, env = "AWS_S3_OBJECT_PREFIX")]
+    prefix: Option<String>,
+    /// Destination prefix written in the manifest.
+    #[clap(long, env = "AWS_S3_DST_OBJECT_PREFIX")]
+    destination_prefix: Option<String>,
+    #[clap(flatten)]
+    filters: find::FindFilters,
+    /// Max concurrent requests made to check the tags or metadata of the objects.
+    #[clap(long, env = "AWS_S3_MAX_CONCURRENT", default_value = "10")]
+    max_concurrent: usize,
+    #[clap(flatten)]
+    manifest: manifest::ManifestOptions,
+    #[clap(flatten)]
+    listing: ListingOptions,
 }
 
 /// Parse a single key-value pair
@@ -139,452 +544,1354 @@ where
 
 pub async fn run(app: App, global: crate::Global) -> Result<()> {
     if global.verbose {
-        aprintln!("S3 Client Version: {}", aws_sdk_s3::meta::PKG_VERSION);
-        aprintln!(
+        aeprintln!("S3 Client Version: {}", aws_sdk_s3::meta::PKG_VERSION);
+        aeprintln!(
             "AWS Region        : {}",
             global
                 .region
                 .as_ref()
                 .ok_or_else(|| eyre!("AWS_REGION not defined"))?
         );
-        aprintln!();
+        aeprintln!();
     }
 
-    let config = crate::aws::get_sdk_config_from_global(global).await?;
-    let client = aws_sdk_s3::Client::new(&config);
+    let config = crate::aws::get_sdk_config_from_global(&global).await?;
+    let client = crate::aws::s3_client(&config, &global);
+    // Only used to resolve the KMS aliases given to the commands that write objects.
+    let kms = crate::aws::kms_client(&config, &global);
+    let output = global.output;
+
+    if app.command.is_bulk() {
+        crate::cancel::install(&global.cancel)?;
+    }
 
     match app.command {
-        Commands::ListBuckets => list_buckets(client).await,
-        Commands::Copy(options) => copy(client, options).await,
-        Commands::CopyList(options) => copy_list(client, options).await,
-        Commands::CountFiles(options) => count_files(client, options).await,
-        Commands::UploadList(options) => upload_list(client, options).await,
+        Commands::ListBuckets => output.list(&list_buckets(client).await?),
+        Commands::Copy(options) => output.one(&copy(client, &kms, options, global.retry).await?),
+        Commands::CopyList(options) => finish(
+            output,
+            copy_list(client, &kms, options, global.retry).await?,
+        ),
+        Commands::Move(options) => output.one(&r#move(client, &kms, options, global.retry).await?),
+        Commands::MoveList(options) => finish(
+            output,
+            move_list(client, &kms, options, global.retry).await?,
+        ),
+        Commands::CountFiles(options) => output.one(&count_files(client, options).await?),
+        Commands::Du(options) => output.list(&du(client, options).await?),
+        Commands::Find(options) => find(client, options, global.retry).await,
+        Commands::UploadList(options) => finish(
+            output,
+            upload_list(client, &kms, options, global.retry).await?,
+        ),
+        Commands::DownloadList(options) => {
+            finish(output, download_list(client, options, global.retry).await?)
+        }
+        Commands::DeleteList(options) if options.dry_run => {
+            let deletions: Vec<_> = delete_list_plan(&options)?
+                .into_iter()
+                .map(|(_, deletion)| deletion)
+                .collect();
+            output.list(&deletions)
+        }
+        Commands::DeleteList(options) => {
+            finish(output, delete_list(client, options, global.retry).await?)
+        }
+        Commands::DeletePrefix(options) if options.dry_run => {
+            output.list(&delete_prefix_plan(&client, &options).await?)
+        }
+        Commands::DeletePrefix(options) => {
+            finish(output, delete_prefix(client, options, global.retry).await?)
+        }
+        Commands::Diff(options) => {
+            let differences = diff(client, &options, global.retry).await?;
+            output.list(&differences)?;
+            if differences.is_empty() {
+                Ok(())
+            } else {
+                Err(eyre!("Found {} difference(s)", differences.len()))
+            }
+        }
+        Commands::Sync(options) if options.dry_run => {
+            let planned: Vec<_> = sync_plan(&client, &options)
+                .await?
+                .iter()
+                .map(|action| action.planned(&options.source, &options.destination))
+                .collect();
+            output.list(&planned)
+        }
+        Commands::Sync(options) => finish(output, sync(client, &kms, options, global.retry).await?),
+    }
+}
+
+/// Prints the summary of a bulk operation, failing if any of its files failed.
+fn finish(output: crate::output::OutputFormat, summary: Summary) -> Result<()> {
+    output.one(&summary)?;
+    summary.ensure_success()
+}
+
+/// A bucket of the account.
+#[derive(Debug, serde::Serialize)]
+pub struct Bucket {
+    pub name: String,
+    pub created_at: String,
+}
+
+impl Record for Bucket {
+    const COLUMNS: &'static [&'static str] = &["Name", "CreatedAt"];
+
+    fn row(&self) -> Vec<String> {
+        vec![self.name.clone(), self.created_at.clone()]
     }
 }
 
-pub async fn list_buckets(client: aws_sdk_s3::Client) -> Result<()> {
+pub async fn list_buckets(client: aws_sdk_s3::Client) -> Result<Vec<Bucket>> {
     let resp = client.list_buckets().send().await?;
 
     log::info!("Getting the list of Buckets");
     let buckets = resp.buckets.ok_or_eyre("No buckets found")?;
 
-    let mut table = new_table();
-    table.set_titles(prettytable::row!["Name", "CreatedAt"]);
+    buckets
+        .into_iter()
+        .map(|bucket| {
+            Ok(Bucket {
+                name: bucket.name.ok_or_eyre("No name")?,
+                created_at: bucket
+                    .creation_date
+                    .ok_or_eyre("No creation date")?
+                    .to_string(),
+            })
+        })
+        .collect()
+}
 
-    for bucket in buckets {
-        table.add_row(prettytable::row![
-            bucket.name.ok_or_eyre("No name")?,
-            bucket.creation_date.ok_or_eyre("No creation date")?
-        ]);
+/// An object copied by `copy`.
+#[derive(Debug, serde::Serialize)]
+pub struct Copied {
+    pub source: String,
+    pub destination: String,
+    pub e_tag: String,
+    pub version_id: Option<String>,
+    pub size: u64,
+}
+
+impl Record for Copied {
+    const COLUMNS: &'static [&'static str] =
+        &["Source", "Destination", "ETag", "VersionId", "Size"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.source.clone(),
+            self.destination.clone(),
+            self.e_tag.clone(),
+            self.version_id.clone().unwrap_or_default(),
+            self.size.to_string(),
+        ]
     }
+}
 
-    aprintln!("{}", table.to_string());
+/// Copy an object from one bucket to another.
+pub async fn copy(
+    client: aws_sdk_s3::Client,
+    kms: &aws_sdk_kms::Client,
+    options: CopyOptions,
+    retry: RetryOptions,
+) -> Result<Copied> {
+    let mut attributes = options.write.attributes(None)?;
+    write::resolve_kms_keys(kms, [&mut attributes]).await?;
+
+    let copied = copy_object(
+        &client,
+        &ObjectCopy {
+            source_bucket: &options.source_bucket,
+            source_key: &options.src,
+            source_version_id: None,
+            destination_bucket: &options.destination_bucket,
+            destination_key: &options.dst,
+            attributes,
+        },
+        &options.multipart,
+        &retry,
+    )
+    .await?;
+
+    Ok(Copied {
+        source: f!("{}/{}", options.source_bucket, options.src),
+        destination: f!("{}/{}", options.destination_bucket, options.dst),
+        e_tag: copied.e_tag,
+        version_id: copied.version_id,
+        size: copied.size,
+    })
+}
 
-    Ok(())
+/// An object moved by `move`.
+#[derive(Debug, serde::Serialize)]
+pub struct Moved {
+    pub source: String,
+    pub destination: String,
+    pub e_tag: String,
+    pub version_id: Option<String>,
+    pub size: u64,
+    pub verification: mv::Verification,
 }
 
-/// Copy an object from one bucket to another.
-pub async fn copy(client: aws_sdk_s3::Client, options: CopyOptions) -> Result<()> {
-    let source_key = f!("{}/{}", options.source_bucket, options.src);
-    let response = client
-        .copy_object()
-        .copy_source(&source_key)
-        .bucket(options.destination_bucket.as_str())
-        .key(options.dst.as_str())
-        .send()
-        .await?;
+impl Record for Moved {
+    const COLUMNS: &'static [&'static str] = &[
+        "Source",
+        "Destination",
+        "ETag",
+        "VersionId",
+        "Size",
+        "Verification",
+    ];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.source.clone(),
+            self.destination.clone(),
+            self.e_tag.clone(),
+            self.version_id.clone().unwrap_or_default(),
+            self.size.to_string(),
+            self.verification.to_string(),
+        ]
+    }
+}
 
-    aprintln!(
-        "Copied from {source_key} to {}/{} with etag {}",
-        options.destination_bucket,
-        options.dst,
-        response
-            .copy_object_result
-            .ok_or_eyre("CopyObjectResult not found")?
-            .e_tag()
-            .ok_or_eyre("ETag not found")?
-    );
-    Ok(())
+/// Move an object from one bucket to another, deleting the source once the copy is verified.
+pub async fn r#move(
+    client: aws_sdk_s3::Client,
+    kms: &aws_sdk_kms::Client,
+    options: MoveOptions,
+    retry: RetryOptions,
+) -> Result<Moved> {
+    let copy = options.copy;
+    let mut attributes = copy.write.attributes(None)?;
+    write::resolve_kms_keys(kms, [&mut attributes]).await?;
+
+    let moved = mv::move_object(
+        &client,
+        &ObjectCopy {
+            source_bucket: &copy.source_bucket,
+            source_key: &copy.src,
+            source_version_id: None,
+            destination_bucket: &copy.destination_bucket,
+            destination_key: &copy.dst,
+            attributes,
+        },
+        &copy.multipart,
+        &retry,
+        options.verify,
+        &mv::Attempts::default(),
+    )
+    .await?;
+
+    Ok(Moved {
+        source: f!("{}/{}", copy.source_bucket, copy.src),
+        destination: f!("{}/{}", copy.destination_bucket, copy.dst),
+        e_tag: moved.copied.e_tag,
+        version_id: moved.copied.version_id,
+        size: moved.copied.size,
+        verification: moved.verification,
+    })
 }
 
 /// Copy a list of objects from one bucket to another.
-pub async fn copy_list(client: aws_sdk_s3::Client, options: CopyListOptions) -> Result<()> {
-    let src = options.src.contents()?;
-    let source_prefix = if let Some(source_prefix) = options.source_prefix.clone() {
-        f!("{}/{}", options.source_bucket, source_prefix)
-    } else {
-        options.source_bucket.clone()
+pub async fn copy_list(
+    client: aws_sdk_s3::Client,
+    kms: &aws_sdk_kms::Client,
+    options: CopyListOptions,
+    retry: RetryOptions,
+) -> Result<Summary> {
+    transfer_list(client, kms, options, None, retry).await
+}
+
+/// Move a list of objects from one bucket to another.
+pub async fn move_list(
+    client: aws_sdk_s3::Client,
+    kms: &aws_sdk_kms::Client,
+    options: MoveListOptions,
+    retry: RetryOptions,
+) -> Result<Summary> {
+    transfer_list(client, kms, options.copy, Some(options.verify), retry).await
+}
+
+/// Copies a list of objects between buckets. With `verify`, every source is deleted once its
+/// copy is verified, turning the copy into a move.
+async fn transfer_list(
+    client: aws_sdk_s3::Client,
+    kms: &aws_sdk_kms::Client,
+    options: CopyListOptions,
+    verify: Option<mv::VerifyMode>,
+    retry: RetryOptions,
+) -> Result<Summary> {
+    let report = Report::new(
+        options.report.as_deref(),
+        match verify {
+            Some(_) => "move-list",
+            None => "copy-list",
+        },
+    );
+    let result = transfer_items(client, kms, options, verify, retry, &report).await;
+    report.write(&result).await;
+    result
+}
+
+async fn transfer_items(
+    client: aws_sdk_s3::Client,
+    kms: &aws_sdk_kms::Client,
+    options: CopyListOptions,
+    verify: Option<mv::VerifyMode>,
+    retry: RetryOptions,
+    report: &Report,
+) -> Result<Summary> {
+    let src = manifest::contents(options.src)?;
+    let source_prefix = options.source_prefix.clone().unwrap_or_default();
+    let destination_prefix = options.destination_prefix.clone().unwrap_or_default();
+    let defaults = options.write.attributes(
+        options
+            .metadata
+            .clone()
+            .map(|metadata| metadata.into_iter().collect()),
+    )?;
+
+    let concurrency = Concurrency::new(options.max_concurrent, options.adaptive_concurrency);
+    // Items are retried by `retry` rather than the SDK, so that the limit sees every throttled
+    // attempt.
+    let client = crate::aws::single_attempt(&client);
+
+    // Parse and validate the whole list before copying anything
+    let mut items = manifest::read(&src, manifest::COPY_LIST, &options.manifest, |record| {
+        let entry = &record.entry;
+        let source_key = f!("{}{}", source_prefix, entry.src);
+        let destination_key = f!(
+            "{}{}",
+            destination_prefix,
+            entry.dst.as_deref().unwrap_or(&entry.src)
+        );
+        if verify.is_some() {
+            mv::check_locations(
+                (&options.source_bucket, &source_key),
+                (&options.destination_bucket, &destination_key),
+            )?;
+        }
+
+        Ok((source_key, destination_key, entry.attributes(&defaults)?))
+    })?;
+    write::resolve_kms_keys(
+        kms,
+        items.iter_mut().map(|(_, (_, _, attributes))| attributes),
+    )
+    .await?;
+
+    let recorder = Recorder::new(
+        options.journal.as_deref(),
+        options.failed_output.as_deref(),
+        manifest::COPY_LIST,
+        &options.manifest,
+    )
+    .await?;
+    let items: Vec<_> = items
+        .into_iter()
+        .filter(|(record, _)| !recorder.is_completed(record))
+        .collect();
+
+    // Spawn a progress logger task in a separate async task
+    let (verb, done) = match verify {
+        Some(_) => ("move", "moved"),
+        None => ("copy", "copied"),
     };
-    let destination_prefix = if let Some(destination_prefix) = options.destination_prefix.clone() {
-        destination_prefix
-    } else {
-        "".to_string()
+    let progress = Progress::new(items.len(), done)
+        .with_throttle(&options.multipart.throttle)
+        .with_concurrency(&concurrency);
+    let reporter = progress.spawn_reporter();
+    let multipart = MultipartOptions {
+        progress: Some(progress.clone()),
+        ..options.multipart.clone()
     };
-    let metadata = options.metadata.unwrap_or_default();
 
-    // Atomic counter for tracking copied files
-    let copied_count = Arc::new(AtomicUsize::new(0));
-    let start_time = Instant::now();
+    aeprintln!(
+        "{} files from bucket {} to bucket {}",
+        if verify.is_some() {
+            "Moving"
+        } else {
+            "Copying"
+        },
+        options.source_bucket,
+        options.destination_bucket
+    );
 
-    // Create a semaphore to control concurrency
-    let semaphore = Arc::new(Semaphore::new(options.max_concurrent));
+    let copy_futures =
+        items
+            .into_iter()
+            .map(|(record, (source_key, destination_key, attributes))| {
+                let client = client.clone();
+                let destination_bucket = options.destination_bucket.clone();
+                let source_bucket = options.source_bucket.clone();
+                let multipart = multipart.clone();
+                let progress = progress.clone();
+                let concurrency = concurrency.clone();
+                let recorder = recorder.clone();
+                let report = report.clone();
+                let retry = retry.clone();
+
+                async move {
+                    let _permit = concurrency.acquire().await;
+                    progress.started();
+                    let start = Instant::now();
+
+                    let copy = ObjectCopy {
+                        source_bucket: &source_bucket,
+                        source_key: &source_key,
+                        source_version_id: record.entry.version_id.as_deref(),
+                        destination_bucket: &destination_bucket,
+                        destination_key: &destination_key,
+                        attributes,
+                    };
+                    let (result, attempts) = match verify {
+                        None => {
+                            let (result, attempts) = retry
+                                .run(|| {
+                                    concurrency
+                                        .observe(copy_object(&client, &copy, &multipart, &retry))
+                                })
+                                .await;
+                            (result.map(|copied| (copied, None)), attempts)
+                        }
+                        Some(mode) => {
+                            let previous = mv::Attempts::default();
+                            let (result, attempts) = retry
+                                .run(|| {
+                                    concurrency.observe(mv::move_object(
+                                        &client, &copy, &multipart, &retry, mode, &previous,
+                                    ))
+                                })
+                                .await;
+                            (
+                                result.map(|moved| (moved.copied, Some(moved.verification))),
+                                attempts,
+                            )
+                        }
+                    };
+                    progress.attempts(attempts);
+                    report.item(|| {
+                        let item = Item::new(
+                            f!("s3://{source_bucket}/{source_key}"),
+                            f!("s3://{destination_bucket}/{destination_key}"),
+                            start,
+                            attempts,
+                            &result,
+                            |(copied, _)| {
+                                (
+                                    Some(copied.e_tag.clone()),
+                                    copied.version_id.clone(),
+                                    copied.size,
+                                )
+                            },
+                        );
+                        match verify {
+                            Some(mode) => item.with_verification(
+                                mode,
+                                result
+                                    .as_ref()
+                                    .ok()
+                                    .and_then(|(_, verification)| *verification),
+                            ),
+                            None => item,
+                        }
+                    });
+
+                    match result {
+                        Ok((copied, Some(verification))) => {
+                            recorder.verified(&record, verification).await;
+                            progress.completed(copied.size);
+                        }
+                        Ok((copied, None)) => {
+                            recorder.completed(&record).await;
+                            progress.completed(copied.size);
+                        }
+                        Err(e) if crate::cancel::is_cancelled(&e) => {
+                            recorder.failed(&record, &e).await;
+                            progress.cancelled();
+                        }
+                        Err(e) => {
+                            progress.println(f!(
+                                "Failed to {verb} from {source_bucket}/{source_key} to \
+                                 {destination_key}: {e}"
+                            ));
+                            recorder.failed(&record, &e).await;
+                            progress.failed();
+                        }
+                    }
+                }
+            });
 
-    let document_lines = src.split("\n");
-    let document_lines_length = document_lines.clone().count();
+    join_all(copy_futures).await;
 
-    // Spawn a progress logger task in a separate async task
-    let copied_count_for_progress = copied_count.clone();
-    let progress_handle = tokio::spawn(async move {
-        loop {
-            sleep(Duration::from_secs(5)).await; // Update every 5 seconds
-            let count = copied_count_for_progress.load(Ordering::Relaxed);
-            let elapsed = start_time.elapsed();
-            let rate = if elapsed.as_secs_f64() > 0.0 {
-                count as f64 / elapsed.as_secs_f64()
-            } else {
-                0.0
-            };
-            let time_remaining = if rate > 0.0 {
-                (document_lines_length - count) as f64 / rate
-            } else {
-                0.0
-            };
-            aprintln!(
-                "Progress: {}/{} files copied in {:.2} seconds ({:.2} files/second) time remaining {:.2} seconds",
-                count,
-                document_lines_length,
-                elapsed.as_secs_f64(),
-                rate,
-                time_remaining
-            );
-        }
-    });
+    // Stop reporting the progress when all copy operations are complete
+    reporter.finish();
+    Ok(progress.summary())
+}
 
-    let mut request = client
-        .copy_object()
-        .bucket(options.destination_bucket.clone())
-        .metadata_directive(aws_sdk_s3::types::MetadataDirective::Replace);
+/// Number of objects under a prefix.
+#[derive(Debug, serde::Serialize)]
+pub struct FileCount {
+    pub bucket: String,
+    pub prefix: Option<String>,
+    pub count: u64,
+}
+
+impl Record for FileCount {
+    const COLUMNS: &'static [&'static str] = &["Bucket", "Prefix", "Count"];
 
-    for (key, value) in metadata {
-        request = request.metadata(key, value);
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.bucket.clone(),
+            self.prefix.clone().unwrap_or_default(),
+            self.count.to_string(),
+        ]
     }
+}
 
-    aprintln!(
-        "Copying files from bucket {} to bucket {}",
-        options.source_bucket,
-        options.destination_bucket
+/// Counts the number of objects in a bucket with a given prefix.
+pub async fn count_files(
+    client: aws_sdk_s3::Client,
+    options: CountFilesOptions,
+) -> Result<FileCount> {
+    aeprintln!(
+        "Counting files in bucket: {} with prefix: {}",
+        options.bucket,
+        options.prefix.as_deref().unwrap_or("(none)")
+    );
+
+    let mut object_count: u64 = 0;
+
+    listing::for_each_page(
+        &client,
+        &options.bucket,
+        options.prefix.as_deref(),
+        &options.listing,
+        |page| object_count += page.len() as u64,
+    )
+    .await?;
+
+    Ok(FileCount {
+        bucket: options.bucket,
+        prefix: options.prefix,
+        count: object_count,
+    })
+}
+
+/// Shows where the bytes of a bucket are, grouped by prefix.
+pub async fn du(client: aws_sdk_s3::Client, options: DuOptions) -> Result<Vec<du::Usage>> {
+    let prefix = options.prefix.unwrap_or_default();
+
+    aeprintln!(
+        "Measuring bucket: {} with prefix: {}",
+        options.bucket,
+        if prefix.is_empty() { "(none)" } else { &prefix }
+    );
+
+    let mut usage = du::usage(
+        &client,
+        &options.bucket,
+        &prefix,
+        options.depth,
+        options.by_storage_class,
+        &options.listing,
+    )
+    .await?;
+    du::sort(&mut usage, options.sort);
+
+    let objects: u64 = usage.iter().map(|row| row.objects).sum();
+    let bytes: u64 = usage.iter().map(|row| row.bytes).sum();
+    aeprintln!(
+        "Total: {} objects, {}",
+        objects,
+        bytesize::ByteSize::b(bytes)
+    );
+
+    Ok(usage)
+}
+
+/// Prints a `copy-list` manifest with the objects of a bucket that match every filter.
+pub async fn find(
+    client: aws_sdk_s3::Client,
+    options: FindOptions,
+    retry: RetryOptions,
+) -> Result<()> {
+    let matcher = find::Matcher::new(&options.filters)?;
+    let prefix = options.prefix.unwrap_or_default();
+    let base = &prefix[..prefix.rfind('/').map_or(0, |index| index + 1)];
+    let destination_prefix = options.destination_prefix.unwrap_or_default();
+
+    aeprintln!(
+        "Searching bucket: {} with prefix: {}",
+        options.bucket,
+        if prefix.is_empty() { "(none)" } else { &prefix }
     );
 
-    let copy_futures = document_lines.map(|line| {
-        let mut request = request.clone();
-        let destination_bucket = options.destination_bucket.clone();
-        let source_bucket = options.source_bucket.clone();
+    if let Some(header) = manifest::copy_list_header(&options.manifest)? {
+        aprintln!("{}", header);
+    }
 
-        // Parse the `line` as if it was a `CSV` line with columns: `file`, `source_prefix`, and
-        // `destination_prefix`.
-        let tuple = line.split(",").collect::<Vec<_>>();
+    let listed = std::cell::Cell::new(0u64);
+    let mut found: u64 = 0;
+
+    let details_client = crate::aws::single_attempt(&client);
+
+    let mut matches =
+        std::pin::pin!(
+            listing::objects(&client, &options.bucket, Some(&prefix), &options.listing)
+                .try_filter(|object| {
+                    listed.set(listed.get() + 1);
+                    futures::future::ready(matcher.matches(object))
+                })
+                .map_ok(|object| {
+                    let client = &details_client;
+                    let bucket = &options.bucket;
+                    let matcher = &matcher;
+                    let retry = &retry;
+
+                    async move {
+                        let key = object.key.unwrap_or_default();
+                        if !matcher.needs_details() {
+                            return Ok(Some(key));
+                        }
+
+                        let (matched, _) = retry
+                            .run(|| matcher.matches_details(client, bucket, &key))
+                            .await;
+                        Ok(matched?.then_some(key))
+                    }
+                })
+                .try_buffer_unordered(options.max_concurrent.max(1))
+        );
 
-        if tuple.len() < 3 {
-            panic!(
-                "Invalid line format: {}. Expected at least 3 columns.",
-                line
+    while let Some(key) = matches.try_next().await? {
+        if let Some(key) = key {
+            found += 1;
+            aprintln!(
+                "{}",
+                manifest::copy_list_line(&key, base, &destination_prefix, &options.manifest)?
             );
         }
+    }
+
+    aeprintln!("Found {} of {} objects", found, listed.get());
+
+    Ok(())
+}
+
+/// Upload a list of local files to an S3 bucket.
+pub async fn upload_list(
+    client: aws_sdk_s3::Client,
+    kms: &aws_sdk_kms::Client,
+    options: UploadListOptions,
+    retry: RetryOptions,
+) -> Result<Summary> {
+    let report = Report::new(options.report.as_deref(), "upload-list");
+    let result = upload_items(client, kms, options, retry, &report).await;
+    report.write(&result).await;
+    result
+}
 
-        let file = tuple[0];
-        let source_prefix = f!("{}/{}", source_bucket, tuple[1]);
-        let destination_prefix = tuple[2];
-
-        if tuple.len() == 4 {
-            let serialized_metadata = tuple[3];
-            let serialized_pairs = serialized_metadata.split(" ").collect::<Vec<_>>();
-            for pair in serialized_pairs {
-                let split_vec: Vec<&str> = pair.split("=").collect::<Vec<_>>();
-                if split_vec.len() != 2 {
-                    continue;
+async fn upload_items(
+    client: aws_sdk_s3::Client,
+    kms: &aws_sdk_kms::Client,
+    options: UploadListOptions,
+    retry: RetryOptions,
+    report: &Report,
+) -> Result<Summary> {
+    let src_contents = manifest::contents(options.src)?;
+    let defaults = options.write.attributes(None)?;
+
+    let concurrency = Concurrency::new(options.max_concurrent, options.adaptive_concurrency);
+    // Items are retried by `retry` rather than the SDK, so that the limit sees every throttled
+    // attempt.
+    let client = crate::aws::single_attempt(&client);
+
+    // Parse and validate the whole list before uploading anything
+    let mut items = manifest::read(
+        &src_contents,
+        manifest::UPLOAD_LIST,
+        &options.manifest,
+        |record| {
+            let entry = &record.entry;
+            let local_path = PathBuf::from(&entry.src);
+            let s3_key = match entry.dst.clone() {
+                Some(key) => key,
+                None => upload::destination_key(
+                    options.destination_prefix.as_deref().unwrap_or_default(),
+                    &local_path,
+                )?,
+            };
+
+            Ok((local_path, s3_key, entry.attributes(&defaults)?))
+        },
+    )?;
+    write::resolve_kms_keys(
+        kms,
+        items.iter_mut().map(|(_, (_, _, attributes))| attributes),
+    )
+    .await?;
+
+    let recorder = Recorder::new(
+        options.journal.as_deref(),
+        options.failed_output.as_deref(),
+        manifest::UPLOAD_LIST,
+        &options.manifest,
+    )
+    .await?;
+    let items: Vec<_> = items
+        .into_iter()
+        .filter(|(record, _)| !recorder.is_completed(record))
+        .collect();
+
+    // Spawn a progress logger task
+    let progress = Progress::new(items.len(), "uploaded")
+        .with_throttle(&options.multipart.throttle)
+        .with_concurrency(&concurrency);
+    let reporter = progress.spawn_reporter();
+    let multipart = MultipartOptions {
+        progress: Some(progress.clone()),
+        ..options.multipart.clone()
+    };
+
+    aeprintln!("Uploading files to bucket {}", options.destination_bucket);
+
+    let upload_futures = items
+        .into_iter()
+        .map(|(record, (local_path, s3_key, attributes))| {
+            let client = client.clone();
+            let destination_bucket = options.destination_bucket.clone();
+            let progress = progress.clone();
+            let concurrency = concurrency.clone();
+            let recorder = recorder.clone();
+            let report = report.clone();
+            let retry = retry.clone();
+            let multipart = multipart.clone();
+            let multipart_threshold = options.multipart_threshold;
+            let content_type_detection = options.detect_content_type;
+
+            async move {
+                let _permit = concurrency.acquire().await;
+                progress.started();
+                let start = Instant::now();
+
+                let upload = ObjectUpload {
+                    path: &local_path,
+                    bucket: &destination_bucket,
+                    key: &s3_key,
+                    attributes,
+                    content_type_detection,
+                };
+                let (upload_result, attempts) = retry
+                    .run(|| {
+                        concurrency.observe(upload_object(
+                            &client,
+                            &upload,
+                            multipart_threshold,
+                            &multipart,
+                            &retry,
+                        ))
+                    })
+                    .await;
+                progress.attempts(attempts);
+                report.item(|| {
+                    Item::new(
+                        local_path.display().to_string(),
+                        f!("s3://{destination_bucket}/{s3_key}"),
+                        start,
+                        attempts,
+                        &upload_result,
+                        |uploaded| {
+                            (
+                                uploaded.e_tag.clone(),
+                                uploaded.version_id.clone(),
+                                uploaded.size,
+                            )
+                        },
+                    )
+                });
+
+                match upload_result {
+                    Ok(uploaded) => {
+                        recorder.completed(&record).await;
+                        progress.completed(uploaded.size);
+                    }
+                    Err(e) if crate::cancel::is_cancelled(&e) => {
+                        recorder.failed(&record, &e).await;
+                        progress.cancelled();
+                    }
+                    Err(e) => {
+                        progress.println(f!("Failed to upload {}: {}", local_path.display(), e));
+                        recorder.failed(&record, &e).await;
+                        progress.failed();
+                    }
                 }
-                request = request.metadata(split_vec[0], split_vec[1]);
             }
-        }
+        });
 
-        let source_key = f!("{}{}", source_prefix, file);
-        let destination_key = f!("{}{}", destination_prefix, file);
+    join_all(upload_futures).await;
 
-        let copied_count = copied_count.clone();
-        let semaphore = semaphore.clone();
+    // Stop reporting the progress when all upload operations are complete
+    reporter.finish();
 
-        async move {
-            // Acquire a permit for the semaphore
-            let _permit = semaphore.acquire().await.unwrap();
+    Ok(progress.summary())
+}
 
-            let response = match request
-                .copy_source(&source_key)
-                .key(destination_key.as_str())
-                .send()
-                .await
-            {
-                Ok(response) => response,
-                Err(err) => {
-                    aprintln!(
-                        "Failed to copy from {source_key} to {destination_key}. Error: {}",
-                        err
-                    );
-                    return Ok(());
-                }
-            };
+/// Download a list of S3 objects to the local disk.
+pub async fn download_list(
+    client: aws_sdk_s3::Client,
+    options: DownloadListOptions,
+    retry: RetryOptions,
+) -> Result<Summary> {
+    let src_contents = manifest::contents(options.src)?;
+    let source_prefix = options.source_prefix.clone().unwrap_or_default();
+
+    let client = crate::aws::single_attempt(&client);
+
+    // Create a semaphore to control concurrency
+    let semaphore = Arc::new(Semaphore::new(options.max_concurrent));
 
-            if let Some(copy_object_result) = response.copy_object_result {
-                if copy_object_result.e_tag.is_none() {
-                    aprintln!("Failed to copy from {source_key}: No ETag found",);
+    // Parse and validate the whole list before downloading anything
+    let items = manifest::read(
+        &src_contents,
+        manifest::DOWNLOAD_LIST,
+        &options.manifest,
+        |record| {
+            let entry = &record.entry;
+            let key = f!("{}{}", source_prefix, entry.src);
+            let version_id = entry.version_id.clone();
+
+            let mut destination = PathBuf::from(
+                entry
+                    .dst
+                    .as_deref()
+                    .ok_or_eyre("missing value for the `dst` field")?,
+            );
+            if is_directory_destination(&destination) {
+                let file_name = key.rsplit('/').next().unwrap_or_default();
+                if file_name.is_empty() {
+                    return Err(eyre!("cannot extract the file name of the key `{key}`"));
                 }
-            } else {
-                aprintln!("Failed to copy from {source_key}: No CopyObjectResult found",);
+                destination.push(file_name);
             }
 
-            copied_count.fetch_add(1, Ordering::Relaxed);
+            Ok((key, version_id, destination))
+        },
+    )?;
+
+    let recorder = Recorder::new(
+        options.journal.as_deref(),
+        options.failed_output.as_deref(),
+        manifest::DOWNLOAD_LIST,
+        &options.manifest,
+    )
+    .await?;
+    let items: Vec<_> = items
+        .into_iter()
+        .filter(|(record, _)| !recorder.is_completed(record))
+        .collect();
 
-            Ok(()) as Result<()>
-        }
-    });
+    // Spawn a progress logger task
+    let progress =
+        Progress::new(items.len(), "downloaded").with_throttle(&options.multipart.throttle);
+    let reporter = progress.spawn_reporter();
+    let multipart = MultipartOptions {
+        progress: Some(progress.clone()),
+        ..options.multipart.clone()
+    };
 
-    _ = join_all(copy_futures).await;
+    aeprintln!("Downloading files from bucket {}", options.source_bucket);
+
+    let download_futures = items
+        .into_iter()
+        .map(|(record, (key, version_id, destination))| {
+            let client = client.clone();
+            let source_bucket = options.source_bucket.clone();
+            let progress = progress.clone();
+            let semaphore = semaphore.clone();
+            let recorder = recorder.clone();
+            let retry = retry.clone();
+            let multipart = multipart.clone();
+            let multipart_threshold = options.multipart_threshold;
+
+            async move {
+                // Acquire a permit for the semaphore
+                let _permit = semaphore.acquire().await.unwrap();
+                progress.started();
+
+                let download = ObjectDownload {
+                    bucket: &source_bucket,
+                    key: &key,
+                    version_id: version_id.as_deref(),
+                    destination: &destination,
+                };
+                let (download_result, attempts) = retry
+                    .run(|| {
+                        download_object(&client, &download, multipart_threshold, &multipart, &retry)
+                    })
+                    .await;
+                progress.attempts(attempts);
+
+                match download_result {
+                    Ok(size) => {
+                        recorder.completed(&record).await;
+                        progress.completed(size);
+                    }
+                    Err(e) if crate::cancel::is_cancelled(&e) => {
+                        recorder.failed(&record, &e).await;
+                        progress.cancelled();
+                    }
+                    Err(e) => {
+                        progress.println(f!(
+                            "Failed to download {}/{} to {}: {}",
+                            source_bucket,
+                            key,
+                            destination.display(),
+                            e
+                        ));
+                        recorder.failed(&record, &e).await;
+                        progress.failed();
+                    }
+                }
+            }
+        });
 
-    // Cancel the progress task when all copy operations are complete
-    progress_handle.abort();
+    join_all(download_futures).await;
 
-    let total_copied = copied_count.load(Ordering::Relaxed);
-    let duration = start_time.elapsed();
-    let rate = total_copied as f64 / duration.as_secs_f64();
+    // Stop reporting the progress when all download operations are complete
+    reporter.finish();
+    Ok(progress.summary())
+}
 
-    aprintln!(
-        "\nCopied {}/{} files in {:.2} seconds ({:.2} files/second)",
-        total_copied,
-        document_lines_length,
-        duration.as_secs_f64(),
-        rate
-    );
+/// Whether a local destination refers to a directory the object should be saved into.
+fn is_directory_destination(destination: &std::path::Path) -> bool {
+    destination
+        .to_string_lossy()
+        .ends_with(std::path::MAIN_SEPARATOR)
+        || destination.is_dir()
+}
 
-    Ok(())
+/// Reads the objects to delete from the manifest of `delete-list`.
+fn delete_list_plan(
+    options: &DeleteListOptions,
+) -> Result<Vec<(manifest::Record, delete::Deletion)>> {
+    let src_contents = manifest::contents(options.src.clone())?;
+    let prefix = options.prefix.clone().unwrap_or_default();
+
+    manifest::read(
+        &src_contents,
+        manifest::DELETE_LIST,
+        &options.manifest,
+        |record| {
+            Ok(delete::Deletion {
+                key: f!("{}{}", prefix, record.entry.src),
+                version_id: record.entry.version_id.clone(),
+            })
+        },
+    )
 }
 
-/// Counts the number of objects in a bucket with a given prefix.
-pub async fn count_files(client: aws_sdk_s3::Client, options: CountFilesOptions) -> Result<()> {
-    aprintln!(
-        "Counting files in bucket: {} with prefix: {}",
-        options.bucket,
-        options.prefix.as_deref().unwrap_or("(none)")
-    );
+/// Delete a list of objects from an S3 bucket.
+pub async fn delete_list(
+    client: aws_sdk_s3::Client,
+    options: DeleteListOptions,
+    retry: RetryOptions,
+) -> Result<Summary> {
+    // Parse and validate the whole list before deleting anything
+    let items = delete_list_plan(&options)?;
+
+    let recorder = Recorder::new(
+        options.journal.as_deref(),
+        options.failed_output.as_deref(),
+        manifest::DELETE_LIST,
+        &options.manifest,
+    )
+    .await?;
+    let items: Vec<_> = items
+        .into_iter()
+        .filter(|(record, _)| !recorder.is_completed(record))
+        .map(|(record, deletion)| (Some(record), deletion))
+        .collect();
 
-    let mut object_count: u64 = 0;
-    let mut continuation_token: Option<String> = None;
+    aeprintln!("Deleting files from bucket {}", options.bucket);
+
+    Ok(delete_objects(
+        &client,
+        &options.bucket,
+        &items,
+        &recorder,
+        options.max_concurrent,
+        &retry,
+    )
+    .await)
+}
 
-    loop {
-        let mut list_objects_req = client.list_objects_v2().bucket(options.bucket.as_str());
+/// Lists the objects, or object versions, that `delete-prefix` deletes.
+pub async fn delete_prefix_plan(
+    client: &aws_sdk_s3::Client,
+    options: &DeletePrefixOptions,
+) -> Result<Vec<delete::Deletion>> {
+    let prefix = options.prefix.clone().unwrap_or_default();
 
-        if let Some(prefix) = options.prefix.as_deref() {
-            list_objects_req = list_objects_req.prefix(prefix);
-        }
+    if options.all_versions {
+        return delete::list_versions(client, &options.bucket, &prefix).await;
+    }
 
-        if let Some(token) = continuation_token {
-            list_objects_req = list_objects_req.continuation_token(token);
-        }
+    Ok(
+        listing::list_objects(client, &options.bucket, Some(&prefix), &options.listing)
+            .await?
+            .into_iter()
+            .filter_map(|object| {
+                Some(delete::Deletion {
+                    key: object.key?,
+                    version_id: None,
+                })
+            })
+            .collect(),
+    )
+}
 
-        let resp = list_objects_req.send().await?;
+/// Delete every object under a prefix of an S3 bucket.
+pub async fn delete_prefix(
+    client: aws_sdk_s3::Client,
+    options: DeletePrefixOptions,
+    retry: RetryOptions,
+) -> Result<Summary> {
+    let location = f!(
+        "s3://{}/{}",
+        options.bucket,
+        options.prefix.as_deref().unwrap_or_default()
+    );
 
-        if let Some(contents) = resp.contents {
-            object_count += contents.len() as u64;
-        }
+    aeprintln!("Listing {}", location);
+    let deletions = delete_prefix_plan(&client, &options).await?;
 
-        if let Some(next_token) = resp.next_continuation_token {
-            continuation_token = Some(next_token);
-        } else {
-            break; // No more pages
-        }
+    let what = if options.all_versions {
+        "object versions and delete markers"
+    } else {
+        "objects"
+    };
+    if !options.yes
+        && !deletions.is_empty()
+        && !delete::confirm(&f!(
+            "Delete {} {} under {}?",
+            deletions.len(),
+            what,
+            location
+        ))?
+    {
+        return Err(eyre!("Aborted, nothing was deleted"));
     }
 
-    aprintln!("Total objects counted: {}", object_count);
+    let items: Vec<_> = deletions
+        .into_iter()
+        .map(|deletion| (None, deletion))
+        .collect();
 
-    Ok(())
+    aeprintln!("Deleting {} {} under {}", items.len(), what, location);
+
+    Ok(delete_objects(
+        &client,
+        &options.bucket,
+        &items,
+        &Recorder::default(),
+        options.max_concurrent,
+        &retry,
+    )
+    .await)
 }
 
-/// Upload a list of local files to an S3 bucket.
-pub async fn upload_list(client: aws_sdk_s3::Client, options: UploadListOptions) -> Result<()> {
-    let src_contents = options.src.contents()?;
-
-    // Atomic counter for tracking uploaded files
-    let uploaded_count = Arc::new(AtomicUsize::new(0));
-    let failed_count = Arc::new(AtomicUsize::new(0));
-    let start_time = Instant::now();
+/// Deletes objects in batches, recording the outcome of each of them. Items that come from a
+/// manifest carry its record.
+async fn delete_objects(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    items: &[(Option<manifest::Record>, delete::Deletion)],
+    recorder: &Recorder,
+    max_concurrent: usize,
+    retry: &RetryOptions,
+) -> Summary {
+    let client = &crate::aws::single_attempt(client);
 
     // Create a semaphore to control concurrency
-    let semaphore = Arc::new(Semaphore::new(options.max_concurrent));
-
-    let document_lines: Vec<_> = src_contents
-        .lines()
-        .filter(|l| !l.trim().is_empty())
-        .collect();
-    let document_lines_length = document_lines.len();
+    let semaphore = Arc::new(Semaphore::new(max_concurrent));
 
     // Spawn a progress logger task
-    let uploaded_count_for_progress = uploaded_count.clone();
-    let failed_count_for_progress = failed_count.clone();
-    let progress_handle = tokio::spawn(async move {
-        loop {
-            sleep(Duration::from_secs(5)).await; // Update every 5 seconds
-            let uploaded = uploaded_count_for_progress.load(Ordering::Relaxed);
-            let failed = failed_count_for_progress.load(Ordering::Relaxed);
-            let elapsed = start_time.elapsed();
-            let total_processed = uploaded + failed;
-            let rate = if elapsed.as_secs_f64() > 0.0 {
-                total_processed as f64 / elapsed.as_secs_f64()
-            } else {
-                0.0
-            };
-            let time_remaining = if rate > 0.0 {
-                (document_lines_length - total_processed) as f64 / rate
-            } else {
-                0.0
-            };
-            aprintln!(
-                "Progress: {}/{} files uploaded, {} failed in {:.2} seconds ({:.2} files/second) time remaining {:.2} seconds",
-                uploaded,
-                document_lines_length,
-                failed,
-                elapsed.as_secs_f64(),
-                rate,
-                time_remaining
-            );
-        }
-    });
-
-    aprintln!("Uploading files to bucket {}", options.destination_bucket);
+    let progress = Progress::new(items.len(), "deleted");
+    let reporter = progress.spawn_reporter();
 
-    let upload_futures = document_lines.into_iter().map(|line| {
-        let client = client.clone();
-        let destination_bucket = options.destination_bucket.clone();
-        let destination_prefix = options.destination_prefix.clone().unwrap_or_default();
-        let uploaded_count = uploaded_count.clone();
-        let failed_count = failed_count.clone();
+    let delete_futures = items.chunks(delete::BATCH_SIZE).map(|batch| {
+        let progress = progress.clone();
         let semaphore = semaphore.clone();
 
         async move {
-            let tuple: Vec<&str> = line.split(',').collect();
-
-            if tuple.is_empty() {
-                aprintln!("Invalid line format: `{}`. Expected at least 1 or 2 columns (local_path, [destination_prefix]).", line);
-                failed_count.fetch_add(1, Ordering::Relaxed);
-                return; // Skip invalid line
+            // Acquire a permit for the semaphore
+            let _permit = semaphore.acquire().await.unwrap();
+            for _ in batch {
+                progress.started();
             }
 
-            let local_path_str = tuple[0].trim();
-            let destination_prefix_str = if tuple.len() == 2 { tuple[1] } else { &destination_prefix };
-            let metadata_str = tuple.get(2).map(|s| s.trim()).unwrap_or("");
-
-            let local_path = PathBuf::from(local_path_str);
-            let file_name = match local_path.file_name().and_then(|n| n.to_str()) {
-                Some(name) => name,
-                None => {
-                    aprintln!("Invalid local path: `{}`. Cannot extract file name.", local_path_str);
-                    failed_count.fetch_add(1, Ordering::Relaxed);
-                    return;
+            let deletions: Vec<_> = batch.iter().map(|(_, deletion)| deletion).collect();
+            let (delete_result, attempts) = retry
+                .run(|| delete::delete_batch(client, bucket, &deletions))
+                .await;
+            progress.attempts(attempts);
+            let cancelled = matches!(&delete_result, Err(e) if crate::cancel::is_cancelled(e));
+
+            for (record, deletion) in batch {
+                let error = match &delete_result {
+                    Ok(errors) => errors.get(deletion).cloned(),
+                    Err(e) => Some(e.to_string()),
+                };
+
+                match error {
+                    Some(e) if cancelled => {
+                        if let Some(record) = record {
+                            recorder.failed(record, &e).await;
+                        }
+                        progress.cancelled();
+                    }
+                    None => {
+                        if let Some(record) = record {
+                            recorder.completed(record).await;
+                        }
+                        progress.completed(0);
+                    }
+                    Some(e) => {
+                        progress.println(f!("Failed to delete {}/{}: {}", bucket, deletion, e));
+                        if let Some(record) = record {
+                            recorder.failed(record, &e).await;
+                        }
+                        progress.failed();
+                    }
                 }
-            };
+            }
 
-            let s3_key = if destination_prefix_str.is_empty() {
-                file_name.to_string()
-            } else {
-                // Ensure no double slash if prefix doesn't end with one
-                if destination_prefix_str.ends_with('/') {
-                     f!("{}{}", destination_prefix_str, file_name)
-                } else {
-                     f!("{}/{}", destination_prefix_str, file_name)
+            // Errors S3 reported for none of the requested deletions still fail the run.
+            if let Ok(errors) = &delete_result {
+                for (deletion, e) in errors {
+                    if !deletions.contains(&deletion) {
+                        progress.println(f!("Failed to delete {}/{}: {}", bucket, deletion, e));
+                        progress.failed();
+                    }
                 }
-            };
+            }
+        }
+    });
+
+    join_all(delete_futures).await;
+
+    // Stop reporting the progress when all delete operations are complete
+    reporter.finish();
+    progress.summary()
+}
 
-            let mut metadata: std::collections::HashMap<String, String> = std::collections::HashMap::new();
-            if !metadata_str.is_empty() {
-                 let pairs = metadata_str.split_whitespace();
-                 for pair in pairs {
-                     let split_pair: Vec<&str> = pair.splitn(2, '=').collect();
-                     if split_pair.len() == 2 {
-                         metadata.insert(split_pair[0].to_string(), split_pair[1].to_string());
-                     } else {
-                         aprintln!("Warning: Invalid metadata pair format in line `{}`: `{}`. Expected key=value.", line, pair);
-                     }
-                 }
+/// Compares two S3 prefixes, returning the keys that don't match.
+pub async fn diff(
+    client: aws_sdk_s3::Client,
+    options: &DiffOptions,
+    retry: RetryOptions,
+) -> Result<Vec<diff::Difference>> {
+    let source = diff::s3_location(&options.source)?;
+    let destination = diff::s3_location(&options.destination)?;
+    let filter = sync::Filter::new(&options.include, &options.exclude)?;
+
+    aeprintln!("Comparing {} with {}", options.source, options.destination);
+
+    let (source_entries, destination_entries) = futures::try_join!(
+        sync::list(&client, &options.source, &options.listing),
+        sync::list(&client, &options.destination, &options.listing)
+    )?;
+
+    let (mut differences, unresolved) = diff::compare_listings(
+        &source_entries,
+        &destination_entries,
+        &options.compare,
+        &filter,
+    );
+
+    let client = crate::aws::single_attempt(&client);
+    let compared: Vec<_> = futures::stream::iter(&unresolved)
+        .map(|key| {
+            let client = &client;
+            let retry = &retry;
+
+            async move {
+                let (result, _) = retry
+                    .run(|| diff::compare_heads(client, source, destination, key, &options.compare))
+                    .await;
+                result
             }
+        })
+        .buffer_unordered(options.max_concurrent.max(1))
+        .try_collect()
+        .await?;
+    differences.extend(compared.into_iter().flatten());
+    differences.sort_by(|a, b| a.key.cmp(&b.key));
+
+    let count = |status| {
+        differences
+            .iter()
+            .filter(|difference| difference.status == status)
+            .count()
+    };
+    aeprintln!(
+        "{} only in source, {} only in destination, {} different, out of {} source and {} destination keys",
+        count(diff::Status::OnlyInSource),
+        count(diff::Status::OnlyInDestination),
+        count(diff::Status::Different),
+        source_entries.len(),
+        destination_entries.len()
+    );
 
-            // Acquire a permit for the semaphore
-            let _permit = match semaphore.acquire().await {
-                 Ok(p) => p,
-                 Err(e) => {
-                     aprintln!("Failed to acquire semaphore permit: {}. Skipping upload for {}", e, local_path_str);
-                     failed_count.fetch_add(1, Ordering::Relaxed);
-                     return;
-                 }
-            };
+    if let Some(path) = options.manifest_output.as_deref() {
+        let (_, source_prefix) = source;
+        let (_, destination_prefix) = destination;
+
+        let mut contents = String::new();
+        if let Some(header) = manifest::copy_list_header(&options.manifest)? {
+            contents.push_str(&header);
+            contents.push('\n');
+        }
+        for difference in differences
+            .iter()
+            .filter(|difference| difference.status != diff::Status::OnlyInDestination)
+        {
+            contents.push_str(&manifest::copy_list_line(
+                &f!("{}{}", source_prefix, difference.key),
+                source_prefix,
+                destination_prefix,
+                &options.manifest,
+            )?);
+            contents.push('\n');
+        }
 
-            let upload_result = async {
-                 // Read file content
-                 let mut file = File::open(&local_path).await.map_err(|e| eyre!("Failed to open file {}: {}", local_path_str, e))?;
-                 let mut contents = Vec::new();
-                 file.read_to_end(&mut contents).await.map_err(|e| eyre!("Failed to read file {}: {}", local_path_str, e))?;
-                 let body = ByteStream::from_path(&local_path).await?;
+        tokio::fs::write(path, contents)
+            .await
+            .wrap_err_with(|| f!("Failed to write manifest {}", path.display()))?;
+    }
 
-                 // Build PutObject request
-                 let mut request = client
-                     .put_object()
-                     .bucket(destination_bucket.as_str())
-                     .key(s3_key.as_str())
-                     .body(body);
+    Ok(differences)
+}
 
-                 for (key, value) in metadata {
-                     request = request.metadata(key, value);
-                 }
+/// Works out what `sync` has to do, without changing anything.
+pub async fn sync_plan(
+    client: &aws_sdk_s3::Client,
+    options: &SyncOptions,
+) -> Result<Vec<sync::Action>> {
+    if matches!(
+        (&options.source, &options.destination),
+        (sync::Location::Local(_), sync::Location::Local(_))
+    ) {
+        return Err(eyre!(
+            "At least one of the source or the destination has to be an S3 prefix"
+        ));
+    }
 
-                 // Send request
-                 request.send().await.map_err(|e| eyre!("S3 PutObject failed for {}: {}", local_path_str, e))?;
+    let filter = sync::Filter::new(&options.include, &options.exclude)?;
+
+    aeprintln!("Comparing {} with {}", options.source, options.destination);
+
+    let (source_entries, destination_entries) = futures::try_join!(
+        sync::list(client, &options.source, &options.listing),
+        sync::list(client, &options.destination, &options.listing)
+    )?;
+
+    sync::plan(
+        &options.source,
+        &source_entries,
+        &options.destination,
+        &destination_entries,
+        options.compare,
+        options.delete,
+        &filter,
+        options.max_concurrent,
+    )
+    .await
+}
 
-                 Ok(()) as Result<()>
-            }
-            .await;
+/// Sync a local directory or S3 prefix into another one.
+pub async fn sync(
+    client: aws_sdk_s3::Client,
+    kms: &aws_sdk_kms::Client,
+    options: SyncOptions,
+    retry: RetryOptions,
+) -> Result<Summary> {
+    let mut attributes = options.write.attributes(None)?;
+    write::resolve_kms_keys(kms, [&mut attributes]).await?;
 
-            match upload_result {
-                Ok(_) => {
-                    uploaded_count.fetch_add(1, Ordering::Relaxed);
-                    // Optionally log success
-                    // aprintln!("Uploaded {} to {}/{}", local_path_str, destination_bucket, s3_key);
+    let actions = sync_plan(&client, &options).await?;
+    let client = crate::aws::single_attempt(&client);
+
+    // Create a semaphore to control concurrency
+    let semaphore = Arc::new(Semaphore::new(options.max_concurrent));
+
+    // Spawn a progress logger task
+    let progress =
+        Progress::new(actions.len(), "synced").with_throttle(&options.multipart.throttle);
+    let reporter = progress.spawn_reporter();
+    let multipart = MultipartOptions {
+        progress: Some(progress.clone()),
+        ..options.multipart.clone()
+    };
+
+    let sync_futures = actions.iter().map(|action| {
+        let client = client.clone();
+        let progress = progress.clone();
+        let semaphore = semaphore.clone();
+        let retry = retry.clone();
+        let options = &options;
+        let multipart = &multipart;
+        let attributes = &attributes;
+
+        async move {
+            // Acquire a permit for the semaphore
+            let _permit = semaphore.acquire().await.unwrap();
+            progress.started();
+
+            let (result, attempts) = retry
+                .run(|| {
+                    sync::apply(
+                        &client,
+                        action,
+                        &options.source,
+                        &options.destination,
+                        options.multipart_threshold,
+                        multipart,
+                        &retry,
+                        attributes,
+                        options.detect_content_type,
+                    )
+                })
+                .await;
+            progress.attempts(attempts);
+
+            match result {
+                Ok(size) => {
+                    log::info!("{}", action.describe(&options.source, &options.destination));
+                    progress.completed(size);
+                }
+                Err(e) if crate::cancel::is_cancelled(&e) => {
+                    progress.cancelled();
                 }
                 Err(e) => {
-                    aprintln!("Failed to upload {}: {}", local_path_str, e);
-                    failed_count.fetch_add(1, Ordering::Relaxed);
+                    progress.println(f!(
+                        "Failed to {}: {}",
+                        action.describe(&options.source, &options.destination),
+                        e
+                    ));
+                    progress.failed();
                 }
             }
         }
     });
 
-    join_all(upload_futures).await;
+    join_all(sync_futures).await;
 
-    // Cancel the progress task when all upload operations are complete
-    progress_handle.abort();
-
-    let total_uploaded = uploaded_count.load(Ordering::Relaxed);
-    let total_failed = failed_count.load(Ordering::Relaxed);
-    let duration = start_time.elapsed();
-    let rate = (total_uploaded + total_failed) as f64 / duration.as_secs_f64();
-
-    aprintln!(
-        "\nUpload Summary: {}/{} files uploaded, {} failed in {:.2} seconds ({:.2} files/second)",
-        total_uploaded,
-        document_lines_length,
-        total_failed,
-        duration.as_secs_f64(),
-        rate
-    );
-
-    if total_failed > 0 {
-        Err(eyre!("{} file(s) failed to upload.", total_failed))
-    } else {
-        Ok(())
-    }
+    // Stop reporting the progress when all sync operations are complete
+    reporter.finish();
+    Ok(progress.summary())
 }
@@ -0,0 +1,192 @@
+use crate::prelude::*;
+use aws_smithy_checksums::http::HttpChecksum;
+use aws_smithy_types::base64;
+use aws_smithy_types::body::SdkBody;
+use aws_smithy_types::byte_stream::ByteStream;
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+/// Algorithm of the checksums sent with every write and compared with the ones S3 returns.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    Crc32c,
+    Crc32,
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    /// The algorithm as the S3 requests take it.
+    pub fn sdk(self) -> aws_sdk_s3::types::ChecksumAlgorithm {
+        match self {
+            ChecksumAlgorithm::Crc32c => aws_sdk_s3::types::ChecksumAlgorithm::Crc32C,
+            ChecksumAlgorithm::Crc32 => aws_sdk_s3::types::ChecksumAlgorithm::Crc32,
+            ChecksumAlgorithm::Sha1 => aws_sdk_s3::types::ChecksumAlgorithm::Sha1,
+            ChecksumAlgorithm::Sha256 => aws_sdk_s3::types::ChecksumAlgorithm::Sha256,
+        }
+    }
+
+    fn hasher(self) -> Box<dyn HttpChecksum> {
+        match self {
+            ChecksumAlgorithm::Crc32c => aws_smithy_checksums::ChecksumAlgorithm::Crc32c,
+            ChecksumAlgorithm::Crc32 => aws_smithy_checksums::ChecksumAlgorithm::Crc32,
+            ChecksumAlgorithm::Sha1 => aws_smithy_checksums::ChecksumAlgorithm::Sha1,
+            ChecksumAlgorithm::Sha256 => aws_smithy_checksums::ChecksumAlgorithm::Sha256,
+        }
+        .into_impl()
+    }
+
+    /// Picks the checksum of this algorithm out of the checksum fields of an S3 response.
+    pub fn pick<'a>(
+        self,
+        crc32: Option<&'a str>,
+        crc32_c: Option<&'a str>,
+        sha1: Option<&'a str>,
+        sha256: Option<&'a str>,
+    ) -> Option<&'a str> {
+        match self {
+            ChecksumAlgorithm::Crc32c => crc32_c,
+            ChecksumAlgorithm::Crc32 => crc32,
+            ChecksumAlgorithm::Sha1 => sha1,
+            ChecksumAlgorithm::Sha256 => sha256,
+        }
+    }
+
+    /// Computes the checksum of an upload body while it gets sent, so the data is only read
+    /// once. A body sent again by a retry starts the checksum over.
+    pub fn body(self, body: ByteStream) -> (ByteStream, BodyChecksum) {
+        let checksum = BodyChecksum::default();
+        let body = body.map({
+            let checksum = checksum.clone();
+            move |body| {
+                let mut body = HashingBody {
+                    inner: body,
+                    hasher: Some(self.hasher()),
+                    checksum: checksum.clone(),
+                };
+                // An empty body may never get polled.
+                if body.inner.is_end_stream() {
+                    body.finish();
+                }
+                SdkBody::from_body_1_x(body)
+            }
+        });
+
+        (body, checksum)
+    }
+
+    /// Checksum of a multipart object as S3 reports it: the checksum of the concatenated
+    /// checksums of every part, in order, followed by the number of parts.
+    pub fn composite(self, parts: &[Vec<u8>]) -> String {
+        let mut hasher = self.hasher();
+        for part in parts {
+            hasher.update(part);
+        }
+
+        f!("{}-{}", encode(&hasher.finalize()), parts.len())
+    }
+}
+
+/// Checksum of an upload body, known once the whole body was sent.
+#[derive(Debug, Clone, Default)]
+pub struct BodyChecksum(Arc<Mutex<Option<Vec<u8>>>>);
+
+impl BodyChecksum {
+    pub fn get(&self) -> Result<Vec<u8>> {
+        self.0
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_eyre("the checksum of the body is unknown, as it wasn't sent in full")
+    }
+}
+
+/// A request body feeding every chunk to a hasher as it gets sent.
+struct HashingBody {
+    inner: SdkBody,
+    /// Taken once the body ends.
+    hasher: Option<Box<dyn HttpChecksum>>,
+    checksum: BodyChecksum,
+}
+
+impl HashingBody {
+    fn finish(&mut self) {
+        if let Some(hasher) = self.hasher.take() {
+            *self.checksum.0.lock().unwrap() = Some(hasher.finalize().to_vec());
+        }
+    }
+}
+
+impl Body for HashingBody {
+    type Data = Bytes;
+    type Error = aws_smithy_types::body::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Self::Error>>> {
+        let this = &mut *self;
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+
+        match &frame {
+            Some(Ok(frame)) => {
+                if let (Some(bytes), Some(hasher)) = (frame.data_ref(), &mut this.hasher) {
+                    hasher.update(bytes);
+                }
+                // The body may not get polled again after its last chunk.
+                if this.inner.is_end_stream() {
+                    this.finish();
+                }
+            }
+            // A body that failed to read has no checksum.
+            Some(Err(_)) => this.hasher = None,
+            None => this.finish(),
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        Body::size_hint(&self.inner)
+    }
+}
+
+/// Encodes a checksum the way S3 returns it.
+pub fn encode(checksum: &[u8]) -> String {
+    base64::encode(checksum)
+}
+
+/// Decodes a checksum returned by S3.
+pub fn decode(checksum: &str) -> Result<Vec<u8>> {
+    base64::decode(checksum).map_err(|e| eyre!("Invalid checksum `{checksum}`: {e}"))
+}
+
+/// Fails unless S3 returned the `expected` checksum for `what`.
+pub fn verify(
+    algorithm: ChecksumAlgorithm,
+    what: &str,
+    expected: &str,
+    returned: Option<&str>,
+) -> Result<()> {
+    match returned {
+        Some(returned) if returned == expected => Ok(()),
+        Some(returned) => Err(eyre!(
+            "{} checksum mismatch for {what}: expected {expected}, S3 returned {returned}",
+            algorithm.sdk().as_str()
+        )),
+        None => Err(eyre!(
+            "S3 returned no {} checksum for {what}",
+            algorithm.sdk().as_str()
+        )),
+    }
+}
@@ -0,0 +1,208 @@
+use crate::prelude::*;
+use crate::retry::is_throttled;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+use tokio::time::Duration;
+
+/// Concurrency an adaptive limit starts at, unless the max is lower.
+const INITIAL_LIMIT: usize = 4;
+
+/// Weight of the latest attempt in the moving average of the latency.
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// How much slower than the fastest moving average attempts can get before the limit stops
+/// growing.
+const LATENCY_TOLERANCE: f64 = 2.0;
+
+/// Share of the gap to the moving average the baseline latency catches up with on each attempt,
+/// so that it follows a shift towards larger objects.
+const BASELINE_DRIFT: f64 = 0.01;
+
+/// Min time between two cuts, so that a burst of throttled attempts sent at the same level
+/// only counts once.
+const DECREASE_COOLDOWN: Duration = Duration::from_secs(1);
+
+/// Limits the number of items of a bulk operation processed at once.
+///
+/// A fixed limit behaves like a semaphore. An adaptive one starts low and grows while the
+/// attempts succeed without slowing down, doubling until the first sign of congestion and by one
+/// after that, and gets halved whenever S3 throttles an attempt.
+#[derive(Debug, Clone)]
+pub struct Concurrency {
+    state: Arc<Mutex<State>>,
+    released: Arc<Notify>,
+    max: usize,
+    adaptive: bool,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: usize,
+    in_flight: usize,
+    /// Whether the limit still doubles on every healthy round.
+    slow_start: bool,
+    /// Successful attempts since the limit last changed.
+    successes: usize,
+    /// Whether an attempt failed since the limit last changed.
+    failed: bool,
+    latency: Option<f64>,
+    baseline: Option<f64>,
+    decreased_at: Option<Instant>,
+}
+
+/// A slot held while an item is processed.
+#[derive(Debug)]
+pub struct Permit {
+    concurrency: Concurrency,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.concurrency.state.lock().unwrap().in_flight -= 1;
+        self.concurrency.released.notify_waiters();
+    }
+}
+
+impl Concurrency {
+    /// Allows up to `max` items at once, starting lower and adapting to how S3 copes with the
+    /// load when `adaptive`.
+    pub fn new(max: usize, adaptive: bool) -> Self {
+        let max = max.max(1);
+        let limit = if adaptive {
+            INITIAL_LIMIT.min(max)
+        } else {
+            max
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(State {
+                limit,
+                in_flight: 0,
+                slow_start: true,
+                successes: 0,
+                failed: false,
+                latency: None,
+                baseline: None,
+                decreased_at: None,
+            })),
+            released: Arc::default(),
+            max,
+            adaptive,
+        }
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Current number of items allowed at once.
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Waits until another item can be processed.
+    pub async fn acquire(&self) -> Permit {
+        loop {
+            // Registered before checking, so that a release in between isn't missed.
+            let released = self.released.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.in_flight < state.limit {
+                    state.in_flight += 1;
+                    return Permit {
+                        concurrency: self.clone(),
+                    };
+                }
+            }
+            released.await;
+        }
+    }
+
+    /// Runs an attempt, adjusting the limit after its outcome and latency.
+    pub async fn observe<T>(&self, attempt: impl Future<Output = Result<T>>) -> Result<T> {
+        let start = Instant::now();
+        let result = attempt.await;
+
+        if self.adaptive {
+            match &result {
+                Ok(_) => self.succeeded(start.elapsed()),
+                Err(e) if is_throttled(e) => self.throttled(),
+                Err(_) => self.state.lock().unwrap().failed = true,
+            }
+        }
+
+        result
+    }
+
+    fn succeeded(&self, elapsed: Duration) {
+        let mut state = self.state.lock().unwrap();
+
+        let elapsed = elapsed.as_secs_f64();
+        let latency = match state.latency {
+            Some(latency) => latency + LATENCY_SMOOTHING * (elapsed - latency),
+            None => elapsed,
+        };
+        let baseline = match state.baseline {
+            Some(baseline) => {
+                let baseline = baseline.min(latency);
+                baseline + BASELINE_DRIFT * (latency - baseline)
+            }
+            None => latency,
+        };
+        state.latency = Some(latency);
+        state.baseline = Some(baseline);
+
+        // Only grow once every item allowed at the current level got a chance to complete.
+        state.successes += 1;
+        if state.successes < state.limit {
+            return;
+        }
+
+        if !state.failed && latency <= baseline * LATENCY_TOLERANCE {
+            let limit = if state.slow_start {
+                state.limit * 2
+            } else {
+                state.limit + 1
+            };
+            state.limit = limit.min(self.max);
+        } else {
+            state.slow_start = false;
+        }
+        state.successes = 0;
+        state.failed = false;
+        drop(state);
+
+        self.released.notify_waiters();
+    }
+
+    fn throttled(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        if state
+            .decreased_at
+            .is_some_and(|at| at.elapsed() < DECREASE_COOLDOWN)
+        {
+            return;
+        }
+
+        let limit = (state.limit / 2).max(1);
+        if limit < state.limit {
+            log::warn!(
+                "S3 is throttling the requests, lowering the concurrency from {} to {}",
+                state.limit,
+                limit
+            );
+        }
+        state.limit = limit;
+        state.slow_start = false;
+        state.successes = 0;
+        state.failed = false;
+        state.decreased_at = Some(Instant::now());
+    }
+}
@@ -0,0 +1,244 @@
+use crate::prelude::*;
+use crate::retry::{classify, RetryOptions};
+use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+use aws_sdk_s3::types::{ChecksumMode, MetadataDirective, TaggingDirective};
+
+use super::checksum::{self, ChecksumAlgorithm};
+use super::multipart::{self, MultipartOptions, ObjectAttributes, MAX_SINGLE_OPERATION_SIZE};
+
+/// A single object copy between two buckets.
+#[derive(Debug, Clone)]
+pub struct ObjectCopy<'a> {
+    pub source_bucket: &'a str,
+    pub source_key: &'a str,
+    /// Version of the source to copy, the latest one when unset.
+    pub source_version_id: Option<&'a str>,
+    pub destination_bucket: &'a str,
+    pub destination_key: &'a str,
+    /// Attributes that replace the ones of the source object. Every unset attribute is
+    /// preserved from the source.
+    pub attributes: ObjectAttributes,
+}
+
+/// Result of a successful copy.
+#[derive(Debug, Clone)]
+pub struct CopiedObject {
+    pub e_tag: String,
+    pub version_id: Option<String>,
+    pub size: u64,
+}
+
+/// Copies an object, falling back to a multipart copy when the source is larger than what
+/// `CopyObject` supports.
+///
+/// With a checksum algorithm, S3 computes the checksum of the copy, which has to match the one
+/// of the source when the source has one.
+pub async fn copy_object(
+    client: &aws_sdk_s3::Client,
+    copy: &ObjectCopy<'_>,
+    options: &MultipartOptions,
+    retry: &RetryOptions,
+) -> Result<CopiedObject> {
+    let source = f!("{}/{}", copy.source_bucket, copy.source_key);
+    let copy_source = match copy.source_version_id {
+        Some(version_id) => f!("{source}?versionId={version_id}"),
+        None => source.clone(),
+    };
+
+    options.throttle.request().await;
+    let head = client
+        .head_object()
+        .bucket(copy.source_bucket)
+        .key(copy.source_key)
+        .set_version_id(copy.source_version_id.map(String::from))
+        .checksum_mode(ChecksumMode::Enabled)
+        .send()
+        .await
+        .map_err(|e| classify(&f!("S3 HeadObject failed for {source}"), e))?;
+    let size = head.content_length.unwrap_or_default().max(0) as u64;
+
+    if size <= MAX_SINGLE_OPERATION_SIZE {
+        let mut request = client
+            .copy_object()
+            .copy_source(&copy_source)
+            .bucket(copy.destination_bucket)
+            .key(copy.destination_key);
+
+        if copy.attributes.replaces_metadata() {
+            // Replacing any header drops all the others, so the rest are carried over.
+            let attributes = attributes_from_head(&head).merge(&copy.attributes);
+            request = request
+                .metadata_directive(MetadataDirective::Replace)
+                .set_metadata(attributes.metadata)
+                .set_content_type(attributes.content_type)
+                .set_content_encoding(attributes.content_encoding)
+                .set_content_disposition(attributes.content_disposition)
+                .set_content_language(attributes.content_language)
+                .set_cache_control(attributes.cache_control);
+        }
+        if let Some(tagging) = copy.attributes.tagging.clone() {
+            request = request
+                .tagging_directive(TaggingDirective::Replace)
+                .tagging(tagging);
+        }
+        request = request
+            .set_storage_class(copy.attributes.storage_class.clone())
+            .set_acl(copy.attributes.acl.clone())
+            .set_server_side_encryption(copy.attributes.server_side_encryption.clone())
+            .set_ssekms_key_id(copy.attributes.sse_kms_key_id.clone())
+            .set_bucket_key_enabled(copy.attributes.bucket_key_enabled)
+            .set_checksum_algorithm(options.checksum_algorithm.map(ChecksumAlgorithm::sdk));
+
+        options.throttle.request().await;
+        let response = request
+            .send()
+            .await
+            .map_err(|e| classify("S3 CopyObject failed", e))?;
+
+        let result = response
+            .copy_object_result
+            .ok_or_eyre("No CopyObjectResult found")?;
+
+        if let Some(algorithm) = options.checksum_algorithm {
+            let destination = f!("{}/{}", copy.destination_bucket, copy.destination_key);
+            let returned = algorithm.pick(
+                result.checksum_crc32.as_deref(),
+                result.checksum_crc32_c.as_deref(),
+                result.checksum_sha1.as_deref(),
+                result.checksum_sha256.as_deref(),
+            );
+            // Checksums of multipart sources depend on their part size, so only full object
+            // checksums can be compared.
+            let expected = algorithm
+                .pick(
+                    head.checksum_crc32.as_deref(),
+                    head.checksum_crc32_c.as_deref(),
+                    head.checksum_sha1.as_deref(),
+                    head.checksum_sha256.as_deref(),
+                )
+                .filter(|expected| !expected.contains('-'));
+
+            match expected {
+                Some(expected) => checksum::verify(algorithm, &destination, expected, returned)?,
+                None if returned.is_none() => {
+                    return Err(eyre!(
+                        "S3 returned no {} checksum for {}",
+                        algorithm.sdk().as_str(),
+                        destination
+                    ));
+                }
+                None => {}
+            }
+        }
+
+        let e_tag = result.e_tag.ok_or_eyre("No ETag found")?;
+
+        return Ok(CopiedObject {
+            e_tag,
+            version_id: response.version_id,
+            size,
+        });
+    }
+
+    log::info!(
+        "{} is {} bytes, copying it with a multipart upload",
+        source,
+        size
+    );
+
+    let mut attributes = attributes_from_head(&head);
+    if copy.attributes.tagging.is_none() {
+        options.throttle.request().await;
+        attributes.tagging = source_tagging(
+            client,
+            copy.source_bucket,
+            copy.source_key,
+            copy.source_version_id,
+        )
+        .await?;
+    }
+    let attributes = attributes.merge(&copy.attributes);
+
+    let response = multipart::copy(
+        client,
+        &copy_source,
+        size,
+        copy.destination_bucket,
+        copy.destination_key,
+        &attributes,
+        options,
+        retry,
+    )
+    .await?;
+
+    Ok(CopiedObject {
+        e_tag: response.e_tag.ok_or_eyre("No ETag found")?,
+        version_id: response.version_id,
+        size,
+    })
+}
+
+/// The attributes of an existing object that a copy should preserve. Like `CopyObject` does,
+/// the encryption and ACL of the source are not carried over.
+fn attributes_from_head(head: &HeadObjectOutput) -> ObjectAttributes {
+    ObjectAttributes {
+        metadata: head.metadata.clone(),
+        content_type: head.content_type.clone(),
+        content_encoding: head.content_encoding.clone(),
+        content_disposition: head.content_disposition.clone(),
+        content_language: head.content_language.clone(),
+        cache_control: head.cache_control.clone(),
+        tagging: None,
+        storage_class: head.storage_class.clone(),
+        ..Default::default()
+    }
+}
+
+/// Gets the tags of an object, encoded as the `x-amz-tagging` header expects them.
+async fn source_tagging(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+) -> Result<Option<String>> {
+    let response = client
+        .get_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .set_version_id(version_id.map(String::from))
+        .send()
+        .await
+        .map_err(|e| classify(&f!("S3 GetObjectTagging failed for {bucket}/{key}"), e))?;
+
+    if response.tag_set.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(encode_tagging(
+        response
+            .tag_set
+            .iter()
+            .map(|tag| (tag.key.as_str(), tag.value.as_str())),
+    )))
+}
+
+/// Encodes tags as the `x-amz-tagging` header expects them.
+pub fn encode_tagging<'a>(tags: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    tags.into_iter()
+        .map(|(key, value)| f!("{}={}", url_encode(key), url_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Percent-encodes everything but the unreserved URL characters.
+pub fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => f!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,166 @@
+use crate::output::Record;
+use crate::prelude::*;
+use crate::retry::classify;
+use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+
+/// Max number of keys `DeleteObjects` accepts in a single request.
+pub const BATCH_SIZE: usize = 1000;
+
+/// An object, or a single version of it, to delete.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize)]
+pub struct Deletion {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<String>,
+}
+
+impl Record for Deletion {
+    const COLUMNS: &'static [&'static str] = &["Key", "VersionId"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.key.clone(),
+            self.version_id.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl std::fmt::Display for Deletion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.version_id {
+            Some(version_id) => write!(f, "{} (version {})", self.key, version_id),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+/// Deletes up to [`BATCH_SIZE`] objects with a single request. The request as a whole only
+/// fails when S3 rejects it; the deletions S3 couldn't do are returned with their error. An
+/// error that doesn't match any deletion of the batch is returned as S3 reported it.
+pub async fn delete_batch(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    batch: &[&Deletion],
+) -> Result<HashMap<Deletion, String>> {
+    let objects = batch
+        .iter()
+        .map(|deletion| {
+            ObjectIdentifier::builder()
+                .key(&deletion.key)
+                .set_version_id(deletion.version_id.clone())
+                .build()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let response = client
+        .delete_objects()
+        .bucket(bucket)
+        .delete(
+            Delete::builder()
+                .set_objects(Some(objects))
+                .quiet(true)
+                .build()?,
+        )
+        .send()
+        .await
+        .map_err(|e| classify(&f!("S3 DeleteObjects failed for {bucket}"), e))?;
+
+    let mut errors = HashMap::new();
+    for error in response.errors.unwrap_or_default() {
+        let reported = Deletion {
+            key: error.key.unwrap_or_default(),
+            version_id: error.version_id,
+        };
+        let reason = f!(
+            "{}: {}",
+            error.code.as_deref().unwrap_or("Unknown"),
+            error.message.as_deref().unwrap_or("no message")
+        );
+
+        // S3 may report a version for a key that was deleted without one.
+        let deletion = batch
+            .iter()
+            .find(|deletion| ***deletion == reported)
+            .or_else(|| {
+                batch
+                    .iter()
+                    .find(|deletion| deletion.version_id.is_none() && deletion.key == reported.key)
+            })
+            .map_or(reported, |deletion| (*deletion).clone());
+        errors.insert(deletion, reason);
+    }
+
+    Ok(errors)
+}
+
+/// Lists every version and delete marker under `prefix`, so that a versioned bucket is left
+/// with nothing under it.
+pub async fn list_versions(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: &str,
+) -> Result<Vec<Deletion>> {
+    let mut deletions = Vec::new();
+    let mut key_marker: Option<String> = None;
+    let mut version_id_marker: Option<String> = None;
+
+    loop {
+        let resp = client
+            .list_object_versions()
+            .bucket(bucket)
+            .set_prefix(Some(prefix.to_string()).filter(|p| !p.is_empty()))
+            .set_key_marker(key_marker)
+            .set_version_id_marker(version_id_marker)
+            .send()
+            .await
+            .map_err(|e| classify(&f!("S3 ListObjectVersions failed for {bucket}"), e))?;
+
+        let versions = resp
+            .versions
+            .unwrap_or_default()
+            .into_iter()
+            .map(|version| (version.key, version.version_id));
+        let delete_markers = resp
+            .delete_markers
+            .unwrap_or_default()
+            .into_iter()
+            .map(|marker| (marker.key, marker.version_id));
+
+        deletions.extend(
+            versions
+                .chain(delete_markers)
+                .filter_map(|(key, version_id)| {
+                    Some(Deletion {
+                        key: key?,
+                        version_id,
+                    })
+                }),
+        );
+
+        if resp.is_truncated.unwrap_or_default() {
+            key_marker = resp.next_key_marker;
+            version_id_marker = resp.next_version_id_marker;
+        } else {
+            break; // No more pages
+        }
+    }
+
+    Ok(deletions)
+}
+
+/// Asks for confirmation on the terminal. Fails when there's no terminal to ask on.
+pub fn confirm(question: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Err(eyre!(
+            "Refusing to delete without confirmation: use `--yes` to run non-interactively"
+        ));
+    }
+
+    anstream::eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,195 @@
+use crate::output::Record;
+use crate::prelude::*;
+use std::collections::BTreeMap;
+
+use super::mv::{checksums, head};
+use super::sync::{Entry, Filter, Location};
+
+/// What is compared for the keys found on both sides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Criterion {
+    Size,
+    /// Multipart uploads with different part sizes have different ETags even when their
+    /// content matches.
+    Etag,
+    /// Compares the checksums both objects have in common. Requires a `HeadObject` request per
+    /// key and side.
+    Checksum,
+    /// Compares the user metadata. Requires a `HeadObject` request per key and side.
+    Metadata,
+}
+
+impl Criterion {
+    /// Whether the criterion can't be checked with the listing alone.
+    pub fn needs_head(self) -> bool {
+        matches!(self, Criterion::Checksum | Criterion::Metadata)
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Criterion::Size => "size",
+            Criterion::Etag => "etag",
+            Criterion::Checksum => "checksum",
+            Criterion::Metadata => "metadata",
+        }
+    }
+}
+
+/// Where a key differs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    OnlyInSource,
+    OnlyInDestination,
+    Different,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::OnlyInSource => "only-in-source",
+            Status::OnlyInDestination => "only-in-destination",
+            Status::Different => "different",
+        }
+    }
+}
+
+/// A key that doesn't match between both sides, relative to their prefixes.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Difference {
+    pub key: String,
+    pub status: Status,
+    /// Criteria the key differs by, for keys found on both sides.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub differences: Vec<&'static str>,
+}
+
+impl Record for Difference {
+    const COLUMNS: &'static [&'static str] = &["Key", "Status", "Differences"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.key.clone(),
+            self.status.as_str().to_string(),
+            self.differences.join(","),
+        ]
+    }
+}
+
+/// Bucket and prefix of a location, which has to be an S3 one.
+pub fn s3_location(location: &Location) -> Result<(&str, &str)> {
+    match location {
+        Location::S3 { bucket, prefix } => Ok((bucket, prefix)),
+        Location::Local(path) => Err(eyre!(
+            "`{}` is not an S3 location, expected `s3://bucket/prefix`",
+            path.display()
+        )),
+    }
+}
+
+/// Compares both listings. Returns the keys that differ, and the keys that look the same but
+/// still have to be checked with [`compare_heads`].
+pub fn compare_listings(
+    source: &BTreeMap<String, Entry>,
+    destination: &BTreeMap<String, Entry>,
+    criteria: &[Criterion],
+    filter: &Filter,
+) -> (Vec<Difference>, Vec<String>) {
+    let mut differences = Vec::new();
+    let mut unresolved = Vec::new();
+
+    for (key, entry) in source.iter().filter(|(key, _)| filter.matches(key)) {
+        let Some(existing) = destination.get(key) else {
+            differences.push(Difference {
+                key: key.clone(),
+                status: Status::OnlyInSource,
+                differences: Vec::new(),
+            });
+            continue;
+        };
+
+        let differ: Vec<_> = criteria
+            .iter()
+            .filter(|criterion| match criterion {
+                Criterion::Size => entry.size != existing.size,
+                Criterion::Etag => entry.e_tag != existing.e_tag,
+                Criterion::Checksum | Criterion::Metadata => false,
+            })
+            .map(|criterion| criterion.as_str())
+            .collect();
+
+        if !differ.is_empty() {
+            differences.push(Difference {
+                key: key.clone(),
+                status: Status::Different,
+                differences: differ,
+            });
+        } else if criteria.iter().any(|criterion| criterion.needs_head()) {
+            unresolved.push(key.clone());
+        }
+    }
+
+    differences.extend(
+        destination
+            .keys()
+            .filter(|key| filter.matches(key) && !source.contains_key(*key))
+            .map(|key| Difference {
+                key: key.clone(),
+                status: Status::OnlyInDestination,
+                differences: Vec::new(),
+            }),
+    );
+
+    (differences, unresolved)
+}
+
+/// Compares the checksums and metadata of `key` on both sides.
+pub async fn compare_heads(
+    client: &aws_sdk_s3::Client,
+    source: (&str, &str),
+    destination: (&str, &str),
+    key: &str,
+    criteria: &[Criterion],
+) -> Result<Option<Difference>> {
+    let (source_bucket, source_prefix) = source;
+    let (destination_bucket, destination_prefix) = destination;
+
+    let source_key = f!("{source_prefix}{key}");
+    let destination_key = f!("{destination_prefix}{key}");
+    let (source, destination) = futures::try_join!(
+        head(client, source_bucket, &source_key, None),
+        head(client, destination_bucket, &destination_key, None)
+    )?;
+
+    let differ: Vec<_> = criteria
+        .iter()
+        .filter(|criterion| match criterion {
+            // Checksums of multipart objects depend on their part count, so only the ones
+            // computed the same way are compared.
+            Criterion::Checksum => {
+                checksums(&source)
+                    .zip(checksums(&destination))
+                    .any(|(source, destination)| match (source, destination) {
+                        (Some(source), Some(destination)) => {
+                            source.rsplit_once('-').map(|(_, parts)| parts)
+                                == destination.rsplit_once('-').map(|(_, parts)| parts)
+                                && source != destination
+                        }
+                        _ => false,
+                    })
+            }
+            Criterion::Metadata => {
+                source.metadata.clone().unwrap_or_default()
+                    != destination.metadata.clone().unwrap_or_default()
+            }
+            Criterion::Size | Criterion::Etag => false,
+        })
+        .map(|criterion| criterion.as_str())
+        .collect();
+
+    Ok((!differ.is_empty()).then(|| Difference {
+        key: key.to_string(),
+        status: Status::Different,
+        differences: differ,
+    }))
+}
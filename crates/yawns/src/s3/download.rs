@@ -0,0 +1,203 @@
+use crate::prelude::*;
+use crate::retry::{classify, RetryOptions};
+use aws_smithy_types::byte_stream::ByteStream;
+use futures::{StreamExt, TryStreamExt};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use super::multipart::{plan_parts, MultipartOptions, Part};
+use super::throttle::ThrottleOptions;
+
+/// A single object download into a local file.
+#[derive(Debug, Clone)]
+pub struct ObjectDownload<'a> {
+    pub bucket: &'a str,
+    pub key: &'a str,
+    pub version_id: Option<&'a str>,
+    pub destination: &'a Path,
+}
+
+/// Downloads an object into a temporary file next to its destination, and renames it into
+/// place once it is complete, so a partially downloaded file never shows up at the destination.
+/// Objects larger than `threshold` are fetched with parallel ranged `GetObject` calls, each of
+/// them retried as `retry` says.
+///
+/// Returns the number of bytes downloaded.
+pub async fn download_object(
+    client: &aws_sdk_s3::Client,
+    download: &ObjectDownload<'_>,
+    threshold: u64,
+    options: &MultipartOptions,
+    retry: &RetryOptions,
+) -> Result<u64> {
+    if let Some(parent) = download.destination.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .wrap_err_with(|| f!("Failed to create directory {}", parent.display()))?;
+    }
+
+    let temporary = temporary_path(download.destination);
+    let result = download_to(client, download, &temporary, threshold, options, retry).await;
+
+    let result = match result {
+        Ok(size) => tokio::fs::rename(&temporary, download.destination)
+            .await
+            .map(|_| size)
+            .wrap_err_with(|| {
+                f!(
+                    "Failed to move {} into {}",
+                    temporary.display(),
+                    download.destination.display()
+                )
+            }),
+        Err(err) => Err(err),
+    };
+
+    if result.is_err() {
+        _ = tokio::fs::remove_file(&temporary).await;
+    }
+
+    result
+}
+
+async fn download_to(
+    client: &aws_sdk_s3::Client,
+    download: &ObjectDownload<'_>,
+    path: &Path,
+    threshold: u64,
+    options: &MultipartOptions,
+    retry: &RetryOptions,
+) -> Result<u64> {
+    options.throttle.request().await;
+    let head = client
+        .head_object()
+        .bucket(download.bucket)
+        .key(download.key)
+        .set_version_id(download.version_id.map(String::from))
+        .send()
+        .await
+        .map_err(|e| {
+            classify(
+                &f!(
+                    "S3 HeadObject failed for {}/{}",
+                    download.bucket,
+                    download.key
+                ),
+                e,
+            )
+        })?;
+    let size = head.content_length.unwrap_or_default().max(0) as u64;
+
+    let file = File::create(path)
+        .await
+        .wrap_err_with(|| f!("Failed to create {}", path.display()))?;
+
+    if size <= threshold {
+        let body = get(client, download, None, &options.throttle).await?;
+        write_body(file, body, &options.throttle).await?;
+        return Ok(size);
+    }
+
+    log::info!(
+        "{}/{} is {} bytes, downloading it with ranged requests",
+        download.bucket,
+        download.key,
+        size
+    );
+
+    file.set_len(size).await?;
+    drop(file);
+
+    // Pin the version, so all parts come from the same object even if it gets overwritten
+    // while it is being downloaded.
+    let download = ObjectDownload {
+        version_id: download.version_id.or(head.version_id.as_deref()),
+        ..download.clone()
+    };
+    let client = &crate::aws::single_attempt(client);
+
+    let progress = &options.parts_progress(size);
+    futures::stream::iter(plan_parts(size, options.part_size))
+        .map(|part| {
+            let download = &download;
+            async move {
+                let (result, _) = retry
+                    .run(|| async move {
+                        let body = get(client, download, Some(part), &options.throttle).await?;
+                        let mut file = OpenOptions::new().write(true).open(path).await?;
+                        file.seek(SeekFrom::Start(part.start)).await?;
+                        write_body(file, body, &options.throttle).await
+                    })
+                    .await;
+                result.map(|_| progress.done(part.len()))
+            }
+        })
+        .buffer_unordered(options.part_concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    Ok(size)
+}
+
+/// Gets the body of the object, or of one of its parts.
+async fn get(
+    client: &aws_sdk_s3::Client,
+    download: &ObjectDownload<'_>,
+    part: Option<Part>,
+    throttle: &ThrottleOptions,
+) -> Result<ByteStream> {
+    throttle.request().await;
+    let response = client
+        .get_object()
+        .bucket(download.bucket)
+        .key(download.key)
+        .set_version_id(download.version_id.map(String::from))
+        .set_range(part.map(|part| part.range()))
+        .send()
+        .await
+        .map_err(|e| {
+            classify(
+                &f!(
+                    "S3 GetObject failed for {}/{}",
+                    download.bucket,
+                    download.key
+                ),
+                e,
+            )
+        })?;
+
+    Ok(response.body)
+}
+
+/// Streams a response body into `file`, no faster than the throttle allows.
+async fn write_body(
+    mut file: File,
+    mut body: ByteStream,
+    throttle: &ThrottleOptions,
+) -> Result<()> {
+    while let Some(bytes) = body
+        .try_next()
+        .await
+        .map_err(|e| Error::Retryable(f!("Failed to read object body: {}", e)))?
+    {
+        throttle.bytes(bytes.len() as u64).await;
+        file.write_all(&bytes).await?;
+    }
+
+    file.flush().await?;
+
+    Ok(())
+}
+
+/// Hidden, unique temporary file in the same directory as `destination`, so that it can be
+/// atomically renamed into place.
+fn temporary_path(destination: &Path) -> PathBuf {
+    let name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    destination.with_file_name(f!(".{}.{:08x}.yawns-part", name, fastrand::u32(..)))
+}
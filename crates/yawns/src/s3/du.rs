@@ -0,0 +1,139 @@
+use crate::output::Record;
+use crate::prelude::*;
+use aws_sdk_s3::types::Object;
+use std::collections::HashMap;
+
+use super::listing::{self, ListingOptions};
+
+/// How the rows of `du` are sorted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortBy {
+    /// Alphabetically by prefix.
+    #[default]
+    Prefix,
+    /// Largest total size first.
+    Size,
+    /// Largest object count first.
+    Count,
+}
+
+/// Usage of a prefix, optionally restricted to a single storage class.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Usage {
+    pub prefix: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_class: Option<String>,
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+impl Record for Usage {
+    const COLUMNS: &'static [&'static str] =
+        &["Prefix", "StorageClass", "Objects", "Size", "Bytes"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.prefix.clone(),
+            self.storage_class.clone().unwrap_or_default(),
+            self.objects.to_string(),
+            bytesize::ByteSize::b(self.bytes).to_string(),
+            self.bytes.to_string(),
+        ]
+    }
+}
+
+/// Running totals keyed by group prefix and storage class.
+#[derive(Debug, Default)]
+struct Totals {
+    groups: HashMap<(String, Option<String>), (u64, u64)>,
+}
+
+impl Totals {
+    fn add(&mut self, object: &Object, base: &str, depth: usize, by_storage_class: bool) {
+        let Some(key) = object.key.as_deref() else {
+            return;
+        };
+
+        let group = group(key, base, depth);
+        let storage_class = by_storage_class.then(|| {
+            object
+                .storage_class
+                .as_ref()
+                .map(|class| class.as_str().to_string())
+                .unwrap_or_else(|| "STANDARD".to_string())
+        });
+
+        let (objects, bytes) = self.groups.entry((group, storage_class)).or_default();
+        *objects += 1;
+        *bytes += object.size.unwrap_or_default().max(0) as u64;
+    }
+}
+
+/// Prefix that `key` is accounted under: `base` followed by, at most, the first `depth`
+/// segments of the rest of the key. Objects that sit above `depth` are accounted under their
+/// own parent prefix.
+fn group(key: &str, base: &str, depth: usize) -> String {
+    let rest = key.strip_prefix(base).unwrap_or(key);
+    let mut segments: Vec<&str> = rest.split('/').collect();
+    // The last segment is the object name.
+    segments.pop();
+
+    let mut group = base.to_string();
+    for segment in segments.into_iter().take(depth) {
+        group.push_str(segment);
+        group.push('/');
+    }
+
+    group
+}
+
+/// Aggregates the object count and size of every object under `prefix`, grouped by prefix up to
+/// `depth` levels deep.
+pub async fn usage(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: &str,
+    depth: usize,
+    by_storage_class: bool,
+    listing: &ListingOptions,
+) -> Result<Vec<Usage>> {
+    // Depth is counted from the "directory" of the prefix, so that `logs/2024` groups
+    // `logs/2024-01/` and `logs/2024-02/` apart.
+    let base = if depth == 0 {
+        prefix
+    } else {
+        &prefix[..prefix.rfind('/').map_or(0, |index| index + 1)]
+    };
+
+    let mut totals = Totals::default();
+    listing::for_each_page(client, bucket, Some(prefix), listing, |page| {
+        for object in &page {
+            totals.add(object, base, depth, by_storage_class);
+        }
+    })
+    .await?;
+
+    Ok(totals
+        .groups
+        .into_iter()
+        .map(|((prefix, storage_class), (objects, bytes))| Usage {
+            prefix,
+            storage_class,
+            objects,
+            bytes,
+        })
+        .collect())
+}
+
+/// Sorts the usage rows in place.
+pub fn sort(rows: &mut [Usage], sort_by: SortBy) {
+    match sort_by {
+        SortBy::Prefix => {
+            rows.sort_by(|a, b| (&a.prefix, &a.storage_class).cmp(&(&b.prefix, &b.storage_class)))
+        }
+        SortBy::Size => rows.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.prefix.cmp(&b.prefix))),
+        SortBy::Count => {
+            rows.sort_by(|a, b| b.objects.cmp(&a.objects).then(a.prefix.cmp(&b.prefix)))
+        }
+    }
+}
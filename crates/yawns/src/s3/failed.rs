@@ -0,0 +1,78 @@
+use crate::prelude::*;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use super::manifest::{self, Line, ManifestFormat, ManifestOptions, Record, Schema};
+
+/// Name of the extra column holding the reason of the failure.
+const ERROR_COLUMN: &str = "error";
+
+/// Writer for the records of a bulk operation manifest that failed.
+///
+/// Each failed record is written back in the format of the manifest, with an extra `error`
+/// column (or field, for JSON Lines) holding the error reason. Bulk commands ignore the columns
+/// and fields they don't know about, so the file can be fed straight back into the same command.
+#[derive(Debug)]
+pub struct FailedOutput {
+    path: PathBuf,
+    file: Mutex<File>,
+    delimiter: u8,
+}
+
+impl FailedOutput {
+    /// Creates (or truncates) the failed-items file at `path`, writing a header when the
+    /// manifest has one.
+    pub async fn create(path: &Path, schema: Schema, options: &ManifestOptions) -> Result<Self> {
+        let mut file = File::create(path)
+            .await
+            .wrap_err_with(|| f!("Failed to create failed-output file {}", path.display()))?;
+
+        if options.manifest_format == ManifestFormat::Csv && options.header {
+            let mut header: Vec<String> = schema.columns.iter().map(|c| c.to_string()).collect();
+            header.push(ERROR_COLUMN.to_string());
+            file.write_all(&manifest::write_row(&header, options.delimiter)?)
+                .await?;
+            file.flush().await?;
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            delimiter: options.delimiter,
+        })
+    }
+
+    /// Path of the failed-items file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends a failed record together with the reason it failed.
+    pub async fn write(&self, record: &Record, reason: impl std::fmt::Display) -> Result<()> {
+        let reason = reason.to_string();
+
+        let row = match record.raw() {
+            Line::Csv(fields) => {
+                let mut fields = fields.clone();
+                // Keep each failure on a single line.
+                fields.push(reason.replace(['\r', '\n'], " "));
+                manifest::write_row(&fields, self.delimiter)?
+            }
+            Line::Json(object) => {
+                let mut object = object.clone();
+                object.insert(ERROR_COLUMN.to_string(), reason.into());
+                let mut row = serde_json::to_vec(&object)?;
+                row.push(b'\n');
+                row
+            }
+        };
+
+        let mut file = self.file.lock().await;
+        file.write_all(&row).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+}
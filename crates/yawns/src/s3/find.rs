@@ -0,0 +1,193 @@
+use crate::prelude::*;
+use crate::retry::classify;
+use aws_sdk_s3::types::{Object, StorageClass};
+use regex::Regex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::multipart::parse_byte_size;
+use super::parse_key_val;
+use super::sync::Filter;
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct FindFilters {
+    /// Only find the keys that match this glob pattern, e.g. `raw/**/*.parquet`. Can be given
+    /// multiple times.
+    #[clap(long)]
+    pub name: Vec<String>,
+    /// Skip the keys that match this glob pattern. Can be given multiple times.
+    #[clap(long)]
+    pub exclude: Vec<String>,
+    /// Only find the keys that match this regular expression. Can be given multiple times, in
+    /// which case a key has to match at least one of them.
+    #[clap(long)]
+    pub regex: Vec<String>,
+    /// Only find objects of at least this size (e.g. `1GB`.)
+    #[clap(long, value_parser = parse_byte_size)]
+    pub min_size: Option<u64>,
+    /// Only find objects of at most this size (e.g. `512MiB`.)
+    #[clap(long, value_parser = parse_byte_size)]
+    pub max_size: Option<u64>,
+    /// Only find objects last modified longer than this ago (e.g. `90d`.)
+    #[clap(long, value_parser = humantime::parse_duration)]
+    pub older_than: Option<Duration>,
+    /// Only find objects last modified less than this ago (e.g. `12h`.)
+    #[clap(long, value_parser = humantime::parse_duration)]
+    pub newer_than: Option<Duration>,
+    /// Only find objects in this storage class. Can be given multiple times.
+    #[clap(long)]
+    pub storage_class: Vec<String>,
+    /// Only find objects with this tag, in the form of a KEY=VALUE pair. Can be given multiple
+    /// times. Requires a `GetObjectTagging` request for every object that passes the other
+    /// filters.
+    #[clap(long, value_parser = parse_key_val::<String, String>, number_of_values = 1)]
+    pub tag: Vec<(String, String)>,
+    /// Only find objects with this metadata, in the form of a KEY=VALUE pair. Can be given
+    /// multiple times. Requires a `HeadObject` request for every object that passes the other
+    /// filters.
+    #[clap(long, value_parser = parse_key_val::<String, String>, number_of_values = 1)]
+    pub metadata: Vec<(String, String)>,
+}
+
+/// Decides whether an object is found, first with what the listing returns, and then, only if
+/// needed, with the tags and metadata of the object.
+#[derive(Debug)]
+pub struct Matcher {
+    filter: Filter,
+    regexes: Vec<Regex>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    /// Unix timestamps the last modified date has to fall between.
+    modified_after: Option<i64>,
+    modified_before: Option<i64>,
+    storage_classes: Vec<String>,
+    tags: Vec<(String, String)>,
+    metadata: Vec<(String, String)>,
+}
+
+impl Matcher {
+    pub fn new(filters: &FindFilters) -> Result<Self> {
+        let regexes = filters
+            .regex
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).wrap_err_with(|| f!("Invalid regular expression `{pattern}`"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let storage_classes = filters
+            .storage_class
+            .iter()
+            .map(|storage_class| {
+                let storage_class = storage_class.to_uppercase();
+                if StorageClass::values().contains(&storage_class.as_str()) {
+                    Ok(storage_class)
+                } else {
+                    Err(eyre!("unknown storage class `{storage_class}`"))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let ago = |age: Duration| now.saturating_sub(age).as_secs() as i64;
+
+        Ok(Self {
+            filter: Filter::new(&filters.name, &filters.exclude)?,
+            regexes,
+            min_size: filters.min_size,
+            max_size: filters.max_size,
+            modified_after: filters.newer_than.map(ago),
+            modified_before: filters.older_than.map(ago),
+            storage_classes,
+            tags: filters.tag.clone(),
+            // Metadata keys come back lowercase from S3.
+            metadata: filters
+                .metadata
+                .iter()
+                .map(|(key, value)| (key.to_lowercase(), value.clone()))
+                .collect(),
+        })
+    }
+
+    /// Whether objects that pass [`Matcher::matches`] still have to be checked with
+    /// [`Matcher::matches_details`].
+    pub fn needs_details(&self) -> bool {
+        !self.tags.is_empty() || !self.metadata.is_empty()
+    }
+
+    /// Checks the filters that can be answered with the listing alone.
+    pub fn matches(&self, object: &Object) -> bool {
+        let Some(key) = object.key.as_deref() else {
+            return false;
+        };
+        let size = object.size.unwrap_or_default().max(0) as u64;
+        let modified = object.last_modified.map(|date| date.secs());
+        let storage_class = object
+            .storage_class
+            .as_ref()
+            .map_or("STANDARD", |class| class.as_str());
+
+        self.filter.matches(key)
+            && (self.regexes.is_empty() || self.regexes.iter().any(|regex| regex.is_match(key)))
+            && self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+            && self
+                .modified_after
+                .is_none_or(|after| modified.is_some_and(|modified| modified >= after))
+            && self
+                .modified_before
+                .is_none_or(|before| modified.is_some_and(|modified| modified <= before))
+            && (self.storage_classes.is_empty()
+                || self
+                    .storage_classes
+                    .iter()
+                    .any(|class| class == storage_class))
+    }
+
+    /// Checks the tag and metadata filters, which need a request per object.
+    pub async fn matches_details(
+        &self,
+        client: &aws_sdk_s3::Client,
+        bucket: &str,
+        key: &str,
+    ) -> Result<bool> {
+        if !self.metadata.is_empty() {
+            let head = client
+                .head_object()
+                .bucket(bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(|e| classify(&f!("S3 HeadObject failed for {bucket}/{key}"), e))?;
+            let metadata = head.metadata.unwrap_or_default();
+
+            if !self
+                .metadata
+                .iter()
+                .all(|(name, value)| metadata.get(name) == Some(value))
+            {
+                return Ok(false);
+            }
+        }
+
+        if !self.tags.is_empty() {
+            let tagging = client
+                .get_object_tagging()
+                .bucket(bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(|e| classify(&f!("S3 GetObjectTagging failed for {bucket}/{key}"), e))?;
+
+            if !self.tags.iter().all(|(name, value)| {
+                tagging
+                    .tag_set
+                    .iter()
+                    .any(|tag| tag.key() == name && tag.value() == value)
+            }) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
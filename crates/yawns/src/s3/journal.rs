@@ -0,0 +1,142 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+/// Outcome of a single manifest line as recorded in the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Completed,
+    Failed,
+}
+
+/// A single journal record. The journal is stored as JSON Lines so that it can be appended to
+/// after every object and still be read back if the process dies halfway through a write.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Entry {
+    status: Status,
+    line: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// How the result was checked, for operations that verify what they wrote.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    verification: Option<String>,
+}
+
+/// Append-only, on-disk record of which manifest lines a bulk operation already processed.
+///
+/// Re-opening a journal replays it, so that lines whose latest entry is `completed` can be
+/// skipped while `failed` and never-seen lines are processed again.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+    entries: HashMap<String, Status>,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, creating it if it doesn't exist.
+    pub async fn open(path: &Path) -> Result<Self> {
+        let mut entries = HashMap::new();
+
+        if path.exists() {
+            let file = File::open(path)
+                .await
+                .wrap_err_with(|| f!("Failed to open journal {}", path.display()))?;
+            let mut lines = BufReader::new(file).lines();
+
+            while let Some(line) = lines.next_line().await? {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // A partially written last entry means the previous run died mid-write; the
+                // line it refers to is simply considered pending.
+                match serde_json::from_str::<Entry>(&line) {
+                    Ok(entry) => {
+                        entries.insert(entry.line, entry.status);
+                    }
+                    Err(err) => log::warn!("Ignoring malformed journal entry `{}`: {}", line, err),
+                }
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .wrap_err_with(|| f!("Failed to open journal {} for writing", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            entries,
+        })
+    }
+
+    /// Path of the journal file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the given line was already completed by a previous run.
+    pub fn is_completed(&self, line: &str) -> bool {
+        self.entries.get(line) == Some(&Status::Completed)
+    }
+
+    /// Number of lines whose latest recorded status is `status`.
+    pub fn count(&self, status: Status) -> usize {
+        self.entries.values().filter(|s| **s == status).count()
+    }
+
+    /// Records that `line` was processed successfully.
+    pub async fn record_completed(&self, line: &str) -> Result<()> {
+        self.append(Entry {
+            status: Status::Completed,
+            line: line.to_string(),
+            error: None,
+            verification: None,
+        })
+        .await
+    }
+
+    /// Records that `line` was processed successfully, and how the result was verified.
+    pub async fn record_verified(
+        &self,
+        line: &str,
+        verification: impl std::fmt::Display,
+    ) -> Result<()> {
+        self.append(Entry {
+            status: Status::Completed,
+            line: line.to_string(),
+            error: None,
+            verification: Some(verification.to_string()),
+        })
+        .await
+    }
+
+    /// Records that `line` failed with the given error.
+    pub async fn record_failed(&self, line: &str, error: impl std::fmt::Display) -> Result<()> {
+        self.append(Entry {
+            status: Status::Failed,
+            line: line.to_string(),
+            error: Some(error.to_string()),
+            verification: None,
+        })
+        .await
+    }
+
+    async fn append(&self, entry: Entry) -> Result<()> {
+        let mut serialized = serde_json::to_string(&entry)?;
+        serialized.push('\n');
+
+        let mut file = self.file.lock().await;
+        file.write_all(serialized.as_bytes()).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+}
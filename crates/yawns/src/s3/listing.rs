@@ -0,0 +1,221 @@
+use crate::prelude::*;
+use crate::retry::classify;
+use aws_sdk_s3::types::Object;
+use futures::{Stream, StreamExt, TryStreamExt};
+use tokio::sync::mpsc;
+
+/// Number of prefixes per concurrent listing after which discovery stops going deeper.
+const PREFIXES_PER_LISTING: usize = 4;
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct ListingOptions {
+    /// Max number of prefixes listed concurrently.
+    #[clap(long, env = "AWS_S3_LIST_CONCURRENCY", default_value = "16")]
+    pub list_concurrency: usize,
+    /// Max number of `/` separated levels explored to split a listing into prefixes that are
+    /// listed in parallel. `0` lists everything with a single request chain.
+    #[clap(long, env = "AWS_S3_LIST_DISCOVERY_DEPTH", default_value = "3")]
+    pub list_discovery_depth: usize,
+}
+
+/// A page of objects, or the error that stopped the listing.
+pub type Page = Result<Vec<Object>>;
+
+/// Lists every object in `bucket` under `prefix`, streaming pages of objects as they arrive.
+///
+/// The key space is first split into prefixes, going down one `/` separated level at a time
+/// with delimited listings, and then every prefix is listed in full concurrently. Pages come
+/// in no particular order. The listing stops early when the receiver gets dropped.
+pub fn list(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    options: &ListingOptions,
+) -> mpsc::Receiver<Page> {
+    let concurrency = options.list_concurrency.max(1);
+    let (tx, rx) = mpsc::channel(concurrency * 2);
+
+    let client = client.clone();
+    let bucket = bucket.to_string();
+    let prefix = prefix.unwrap_or_default().to_string();
+    let depth = options.list_discovery_depth;
+
+    tokio::spawn(async move {
+        if let Err(err) = run(&client, &bucket, prefix, depth, concurrency, &tx).await {
+            // The receiver may be gone already, in which case nobody cares about the error.
+            let _ = tx.send(Err(err)).await;
+        }
+    });
+
+    rx
+}
+
+async fn run(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: String,
+    depth: usize,
+    concurrency: usize,
+    tx: &mpsc::Sender<Page>,
+) -> Result<()> {
+    let mut prefixes = vec![prefix];
+
+    for _ in 0..depth {
+        if prefixes.len() >= concurrency * PREFIXES_PER_LISTING {
+            break;
+        }
+
+        let levels: Vec<(Vec<Object>, Vec<String>)> = futures::stream::iter(prefixes)
+            .map(|prefix| async move { list_level(client, bucket, &prefix).await })
+            .buffer_unordered(concurrency)
+            .try_collect()
+            .await?;
+
+        let mut next = Vec::new();
+        for (objects, common_prefixes) in levels {
+            if !objects.is_empty() && tx.send(Ok(objects)).await.is_err() {
+                return Ok(());
+            }
+            next.extend(common_prefixes);
+        }
+
+        // Everything was found while discovering.
+        if next.is_empty() {
+            return Ok(());
+        }
+        prefixes = next;
+    }
+
+    futures::stream::iter(prefixes)
+        .map(|prefix| async move { list_prefix(client, bucket, &prefix, tx).await })
+        .buffer_unordered(concurrency)
+        .try_collect::<Vec<()>>()
+        .await?;
+
+    Ok(())
+}
+
+/// Lists every object under `prefix` with a single request chain, sending each page to `tx`.
+async fn list_prefix(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: &str,
+    tx: &mpsc::Sender<Page>,
+) -> Result<()> {
+    let mut continuation_token: Option<String> = None;
+
+    loop {
+        crate::cancel::check()?;
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .set_prefix(Some(prefix.to_string()).filter(|p| !p.is_empty()))
+            .set_continuation_token(continuation_token)
+            .send()
+            .await
+            .map_err(|e| classify(&f!("S3 ListObjectsV2 failed for {bucket}"), e))?;
+
+        if let Some(contents) = resp.contents {
+            if tx.send(Ok(contents)).await.is_err() {
+                return Ok(());
+            }
+        }
+
+        if let Some(next_token) = resp.next_continuation_token {
+            continuation_token = Some(next_token);
+        } else {
+            break; // No more pages
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists a single level of `bucket` under `prefix`, using `/` as the delimiter. Returns the
+/// objects found directly under it, and its common prefixes.
+async fn list_level(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: &str,
+) -> Result<(Vec<Object>, Vec<String>)> {
+    let mut objects = Vec::new();
+    let mut prefixes = Vec::new();
+    let mut continuation_token: Option<String> = None;
+
+    loop {
+        crate::cancel::check()?;
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .set_prefix(Some(prefix.to_string()).filter(|p| !p.is_empty()))
+            .delimiter("/")
+            .set_continuation_token(continuation_token)
+            .send()
+            .await
+            .map_err(|e| classify(&f!("S3 ListObjectsV2 failed for {bucket}"), e))?;
+
+        objects.extend(resp.contents.unwrap_or_default());
+        prefixes.extend(
+            resp.common_prefixes
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|common_prefix| common_prefix.prefix),
+        );
+
+        if let Some(next_token) = resp.next_continuation_token {
+            continuation_token = Some(next_token);
+        } else {
+            break; // No more pages
+        }
+    }
+
+    Ok((objects, prefixes))
+}
+
+/// Calls `f` with every page of objects in `bucket` under `prefix`, so that huge listings can
+/// be processed without holding them in memory.
+pub async fn for_each_page(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    options: &ListingOptions,
+    mut f: impl FnMut(Vec<Object>),
+) -> Result<()> {
+    let mut pages = list(client, bucket, prefix, options);
+
+    while let Some(page) = pages.recv().await {
+        f(page?);
+    }
+
+    Ok(())
+}
+
+/// Streams every object in `bucket` under `prefix` one at a time, in no particular order.
+pub fn objects(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    options: &ListingOptions,
+) -> impl Stream<Item = Result<Object>> {
+    let pages = list(client, bucket, prefix, options);
+
+    futures::stream::unfold(pages, |mut pages| async move {
+        pages.recv().await.map(|page| (page, pages))
+    })
+    .map_ok(|page| futures::stream::iter(page.into_iter().map(Ok)))
+    .try_flatten()
+}
+
+/// Lists every object in `bucket` under `prefix`, in no particular order.
+pub async fn list_objects(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    options: &ListingOptions,
+) -> Result<Vec<Object>> {
+    let mut objects = Vec::new();
+
+    for_each_page(client, bucket, prefix, options, |page| objects.extend(page)).await?;
+
+    Ok(objects)
+}
@@ -0,0 +1,608 @@
+use crate::prelude::*;
+use aws_sdk_s3::types::{ObjectCannedAcl, StorageClass};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use super::copy::encode_tagging;
+use super::multipart::ObjectAttributes;
+use super::upload::destination_key;
+use super::write::{self, Sse};
+
+/// Max number of invalid lines listed when a manifest is rejected.
+const MAX_REPORTED_ERRORS: usize = 20;
+
+/// Format of the manifest given to a bulk command.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ManifestFormat {
+    /// Delimited columns, in the order documented by each command.
+    #[default]
+    Csv,
+    /// One JSON object per line with `src`, `dst`, `metadata`, `tags`, `content_type`,
+    /// `content_encoding`, `cache_control`, `storage_class`, `acl`, `sse`, `sse_kms_key_id`,
+    /// `bucket_key_enabled` and `version_id` fields.
+    Jsonl,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct ManifestOptions {
+    /// Format of the manifest.
+    #[clap(long, env = "AWS_S3_MANIFEST_FORMAT", value_enum, default_value_t)]
+    pub manifest_format: ManifestFormat,
+    /// Treat the first row of a CSV manifest as a header, and map the columns by name instead
+    /// of by position.
+    #[clap(long, env = "AWS_S3_MANIFEST_HEADER")]
+    pub header: bool,
+    /// Column delimiter of a CSV manifest. Use `tab` (or `\t`) for TSV files.
+    #[clap(long, env = "AWS_S3_MANIFEST_DELIMITER", default_value = ",", value_parser = parse_delimiter)]
+    pub delimiter: u8,
+}
+
+fn parse_delimiter(s: &str) -> std::result::Result<u8, String> {
+    match s {
+        "tab" | "\\t" | "\t" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(f!(
+            "invalid delimiter `{s}`: expected a single ASCII character"
+        )),
+    }
+}
+
+/// An invalid line of a manifest, and what's wrong with it.
+type LineError = (u64, String);
+
+/// Columns understood by a bulk command, in positional order, and how they map to an
+/// [`Entry`].
+#[derive(Debug, Clone, Copy)]
+pub struct Schema {
+    pub columns: &'static [&'static str],
+    /// Number of leading columns that every line has to have, even if empty.
+    pub required: usize,
+    entry: fn(&Columns) -> Result<Entry>,
+}
+
+/// Manifest of `copy-list`.
+pub const COPY_LIST: Schema = Schema {
+    columns: &[
+        "file",
+        "source_prefix",
+        "destination_prefix",
+        "metadata",
+        "storage_class",
+        "content_type",
+        "cache_control",
+        "tags",
+        "acl",
+        "sse",
+        "sse_kms_key_id",
+        "bucket_key_enabled",
+        "content_encoding",
+    ],
+    required: 3,
+    entry: |columns| {
+        let file = columns.require("file")?;
+        Ok(Entry {
+            src: f!(
+                "{}{}",
+                columns.get("source_prefix").unwrap_or_default(),
+                file
+            ),
+            dst: Some(f!(
+                "{}{}",
+                columns.get("destination_prefix").unwrap_or_default(),
+                file
+            )),
+            metadata: parse_metadata(columns.get("metadata").unwrap_or_default())?,
+            ..write_settings(columns)?
+        })
+    },
+};
+
+/// Manifest of `upload-list`.
+pub const UPLOAD_LIST: Schema = Schema {
+    columns: &[
+        "local_path",
+        "destination_prefix",
+        "metadata",
+        "storage_class",
+        "content_type",
+        "cache_control",
+        "tags",
+        "acl",
+        "sse",
+        "sse_kms_key_id",
+        "bucket_key_enabled",
+        "content_encoding",
+    ],
+    required: 1,
+    entry: |columns| {
+        let local_path = columns.require("local_path")?;
+        let dst = match columns.get("destination_prefix") {
+            Some(prefix) => Some(destination_key(prefix, Path::new(local_path))?),
+            None => None,
+        };
+        Ok(Entry {
+            src: local_path.to_string(),
+            dst,
+            metadata: parse_metadata(columns.get("metadata").unwrap_or_default())?,
+            ..write_settings(columns)?
+        })
+    },
+};
+
+/// Reads the optional columns that override the settings of a written object. Tags are
+/// given like metadata, as space separated `key=value` pairs.
+fn write_settings(columns: &Columns) -> Result<Entry> {
+    let bucket_key_enabled = match columns.get("bucket_key_enabled") {
+        Some(value) => Some(value.parse().map_err(|_| {
+            eyre!("invalid value `{value}` for the `bucket_key_enabled` column, expected true or false")
+        })?),
+        None => None,
+    };
+
+    Ok(Entry {
+        tags: parse_metadata(columns.get("tags").unwrap_or_default())?,
+        content_type: columns.get("content_type").map(String::from),
+        content_encoding: columns.get("content_encoding").map(String::from),
+        cache_control: columns.get("cache_control").map(String::from),
+        storage_class: columns.get("storage_class").map(String::from),
+        acl: columns.get("acl").map(String::from),
+        sse: columns.get("sse").map(String::from),
+        sse_kms_key_id: columns.get("sse_kms_key_id").map(String::from),
+        bucket_key_enabled,
+        ..Default::default()
+    })
+}
+
+/// Manifest of `download-list`.
+pub const DOWNLOAD_LIST: Schema = Schema {
+    columns: &["key", "local_destination", "version_id"],
+    required: 2,
+    entry: |columns| {
+        Ok(Entry {
+            src: columns.require("key")?.to_string(),
+            dst: Some(columns.require("local_destination")?.to_string()),
+            version_id: columns.get("version_id").map(String::from),
+            ..Default::default()
+        })
+    },
+};
+
+/// Manifest of `delete-list`.
+pub const DELETE_LIST: Schema = Schema {
+    columns: &["key", "version_id"],
+    required: 1,
+    entry: |columns| {
+        Ok(Entry {
+            src: columns.require("key")?.to_string(),
+            version_id: columns.get("version_id").map(String::from),
+            ..Default::default()
+        })
+    },
+};
+
+/// Fields of a JSON Lines manifest line, the ones of [`Entry`].
+const JSONL_FIELDS: &[&str] = &[
+    "src",
+    "dst",
+    "metadata",
+    "tags",
+    "content_type",
+    "content_encoding",
+    "cache_control",
+    "storage_class",
+    "acl",
+    "sse",
+    "sse_kms_key_id",
+    "bucket_key_enabled",
+    "version_id",
+];
+
+/// What a single manifest line asks a bulk command to do, whatever the format of the manifest.
+/// Every field but `src` is optional, and overrides the command-level defaults when set.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct Entry {
+    /// Object key or local path to read from.
+    pub src: String,
+    /// Object key or local path to write to. Each command works it out from `src` when unset.
+    pub dst: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub cache_control: Option<String>,
+    pub storage_class: Option<String>,
+    pub acl: Option<String>,
+    pub sse: Option<String>,
+    pub sse_kms_key_id: Option<String>,
+    pub bucket_key_enabled: Option<bool>,
+    pub version_id: Option<String>,
+}
+
+impl Entry {
+    /// Attributes of the object written for this entry, on top of the command-level ones. The
+    /// metadata of both is merged, while every other setting of the entry replaces the
+    /// command-level one.
+    pub fn attributes(&self, defaults: &ObjectAttributes) -> Result<ObjectAttributes> {
+        let mut merged = defaults.metadata.clone().unwrap_or_default();
+        merged.extend(self.metadata.clone());
+
+        let mut sse = self.sse.as_deref().map(Sse::parse).transpose()?;
+        if self.sse_kms_key_id.is_some() {
+            sse = sse.or(Some(Sse::AwsKms));
+        }
+        let mut defaults = defaults.clone();
+        if sse.is_some_and(|sse| sse != Sse::AwsKms) {
+            // The command-level KMS settings don't apply to a line with another encryption.
+            defaults.sse_kms_key_id = None;
+            defaults.bucket_key_enabled = None;
+        }
+
+        let mut attributes = defaults.merge(&ObjectAttributes {
+            metadata: (!merged.is_empty()).then_some(merged),
+            content_type: self.content_type.clone(),
+            content_encoding: self.content_encoding.clone(),
+            cache_control: self.cache_control.clone(),
+            tagging: (!self.tags.is_empty()).then(|| {
+                encode_tagging(
+                    self.tags
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.as_str())),
+                )
+            }),
+            storage_class: match self.storage_class.as_deref() {
+                Some(storage_class) => Some(StorageClass::from(
+                    write::parse_storage_class(storage_class)
+                        .map_err(|e| eyre!(e))?
+                        .as_str(),
+                )),
+                None => None,
+            },
+            acl: match self.acl.as_deref() {
+                Some(acl) => Some(ObjectCannedAcl::from(
+                    write::parse_acl(acl).map_err(|e| eyre!(e))?.as_str(),
+                )),
+                None => None,
+            },
+            server_side_encryption: sse.map(Sse::sdk),
+            sse_kms_key_id: self.sse_kms_key_id.clone(),
+            bucket_key_enabled: self.bucket_key_enabled,
+            ..Default::default()
+        });
+        write::check_encryption(&mut attributes)?;
+
+        Ok(attributes)
+    }
+}
+
+/// The fields of a CSV line, looked up by the column names of a schema.
+struct Columns<'a> {
+    schema: &'a Schema,
+    fields: &'a [String],
+}
+
+impl Columns<'_> {
+    /// Value of the given column, if present and not empty.
+    fn get(&self, column: &str) -> Option<&str> {
+        self.schema
+            .columns
+            .iter()
+            .position(|c| *c == column)
+            .and_then(|index| self.fields.get(index))
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Value of the given column, failing when it is missing or empty.
+    fn require(&self, column: &str) -> Result<&str> {
+        self.get(column)
+            .ok_or_else(|| eyre!("missing value for the `{column}` column"))
+    }
+}
+
+/// A manifest line as it was read, so that it can be written back in the same format.
+#[derive(Debug, Clone)]
+pub enum Line {
+    /// The values of every column of the schema, in order.
+    Csv(Vec<String>),
+    Json(serde_json::Map<String, serde_json::Value>),
+}
+
+/// A single line of a manifest.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// Line of the manifest the record was read from.
+    pub line: u64,
+    pub entry: Entry,
+    raw: Line,
+    key: String,
+}
+
+impl Record {
+    /// The line as it was read.
+    pub fn raw(&self) -> &Line {
+        &self.raw
+    }
+
+    /// Normalized representation of the record, used to identify it across runs.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+/// Serializes a row with the given delimiter, quoting fields as needed.
+pub fn write_row(fields: &[String], delimiter: u8) -> Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+    writer.write_record(fields)?;
+
+    Ok(writer.into_inner()?)
+}
+
+/// Header row of a `copy-list` manifest, when the manifest options ask for one.
+pub fn copy_list_header(options: &ManifestOptions) -> Result<Option<String>> {
+    if options.manifest_format != ManifestFormat::Csv || !options.header {
+        return Ok(None);
+    }
+
+    let columns: Vec<String> = COPY_LIST.columns[..COPY_LIST.required]
+        .iter()
+        .map(|column| column.to_string())
+        .collect();
+
+    Ok(Some(
+        String::from_utf8(write_row(&columns, options.delimiter)?)?
+            .trim_end()
+            .to_string(),
+    ))
+}
+
+/// `copy-list` manifest line that copies `key` to `destination_prefix`, keeping the part of the
+/// key below `base`.
+pub fn copy_list_line(
+    key: &str,
+    base: &str,
+    destination_prefix: &str,
+    options: &ManifestOptions,
+) -> Result<String> {
+    let file = key.strip_prefix(base).unwrap_or(key);
+
+    match options.manifest_format {
+        ManifestFormat::Csv => {
+            let fields = [
+                file.to_string(),
+                base.to_string(),
+                destination_prefix.to_string(),
+            ];
+            Ok(String::from_utf8(write_row(&fields, options.delimiter)?)?
+                .trim_end()
+                .to_string())
+        }
+        ManifestFormat::Jsonl => Ok(serde_json::json!({
+            "src": key,
+            "dst": f!("{destination_prefix}{file}"),
+        })
+        .to_string()),
+    }
+}
+
+/// Reads a whole manifest from a file or stdin. Unlike `FileOrStdin::contents`, the end of the
+/// input isn't trimmed, so that the last key keeps its trailing spaces.
+pub fn contents(src: clap_stdin::FileOrStdin) -> Result<String> {
+    let mut contents = String::new();
+    src.into_reader()?.read_to_string(&mut contents)?;
+
+    Ok(contents)
+}
+
+/// Parses a whole manifest and converts every record with `f`. Every line is checked before
+/// returning, so that all the problems are reported at once, and before any network work starts.
+pub fn read<T>(
+    contents: &str,
+    schema: Schema,
+    options: &ManifestOptions,
+    mut f: impl FnMut(&Record) -> Result<T>,
+) -> Result<Vec<(Record, T)>> {
+    let (records, mut errors) = match options.manifest_format {
+        ManifestFormat::Csv => parse_csv(contents, schema, options)?,
+        ManifestFormat::Jsonl => parse_jsonl(contents),
+    };
+
+    let mut items = Vec::with_capacity(records.len());
+    for record in records {
+        match f(&record) {
+            Ok(item) => items.push((record, item)),
+            Err(err) => errors.push((record.line, err.to_string())),
+        }
+    }
+
+    errors.sort_by_key(|(line, _)| *line);
+    report(errors)?;
+
+    Ok(items)
+}
+
+/// Splits a CSV manifest into records, collecting the lines that can't be parsed.
+fn parse_csv(
+    contents: &str,
+    schema: Schema,
+    options: &ManifestOptions,
+) -> Result<(Vec<Record>, Vec<LineError>)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(options.header)
+        .delimiter(options.delimiter)
+        .flexible(true)
+        .from_reader(contents.as_bytes());
+
+    let indices: Vec<Option<usize>> = if options.header {
+        let headers = reader.headers()?.clone();
+        let indices: Vec<_> = schema
+            .columns
+            .iter()
+            .map(|column| headers.iter().position(|h| h.trim() == *column))
+            .collect();
+
+        let missing: Vec<_> = schema.columns[..schema.required]
+            .iter()
+            .zip(&indices)
+            .filter(|(_, index)| index.is_none())
+            .map(|(column, _)| f!("`{column}`"))
+            .collect();
+        if !missing.is_empty() {
+            return Err(eyre!(
+                "Invalid manifest: the header is missing the {} column(s)",
+                missing.join(", ")
+            ));
+        }
+
+        indices
+    } else {
+        (0..schema.columns.len()).map(Some).collect()
+    };
+
+    let mut records = Vec::new();
+    let mut errors = Vec::new();
+
+    for row in reader.records() {
+        let row = match row {
+            Ok(row) => row,
+            Err(err) => {
+                let line = err.position().map(|p| p.line()).unwrap_or_default();
+                errors.push((line, err.to_string()));
+                continue;
+            }
+        };
+        let line = row.position().map(|p| p.line()).unwrap_or_default();
+
+        // Lines are positional unless there's a header, so the required columns have to be
+        // there even if empty. With a header, the columns were already checked above.
+        if !options.header && row.len() < schema.required {
+            errors.push((
+                line,
+                f!(
+                    "expected at least {} columns ({}), found {}",
+                    schema.required,
+                    schema.columns[..schema.required].join(", "),
+                    row.len()
+                ),
+            ));
+            continue;
+        }
+
+        let fields: Vec<String> = indices
+            .iter()
+            .map(|index| {
+                index
+                    .and_then(|index| row.get(index))
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .collect();
+
+        let columns = Columns {
+            schema: &schema,
+            fields: &fields,
+        };
+        let entry = match (schema.entry)(&columns) {
+            Ok(entry) => entry,
+            Err(err) => {
+                errors.push((line, err.to_string()));
+                continue;
+            }
+        };
+
+        let key = String::from_utf8(write_row(&fields, options.delimiter)?)?
+            .trim_end_matches(['\r', '\n'])
+            .to_string();
+
+        records.push(Record {
+            line,
+            entry,
+            raw: Line::Csv(fields),
+            key,
+        });
+    }
+
+    Ok((records, errors))
+}
+
+/// Splits a JSON Lines manifest into records, collecting the lines that can't be parsed.
+fn parse_jsonl(contents: &str) -> (Vec<Record>, Vec<LineError>) {
+    let mut records = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, text) in contents.lines().enumerate() {
+        let line = index as u64 + 1;
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let parsed = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(text)
+            .and_then(|object| {
+                let entry = serde_json::from_value::<Entry>(object.clone().into())?;
+                Ok((object, entry))
+            });
+        let (object, entry) = match parsed {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                errors.push((line, err.to_string()));
+                continue;
+            }
+        };
+        if entry.src.is_empty() {
+            errors.push((line, "missing value for the `src` field".to_string()));
+            continue;
+        }
+
+        // Objects are re-serialized so that formatting changes don't affect the journal, and
+        // only the known fields are kept, so that a line of a failed output, with its `error`
+        // field, matches the line it was written from.
+        let key = serde_json::Value::Object(
+            object
+                .iter()
+                .filter(|(field, _)| JSONL_FIELDS.contains(&field.as_str()))
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect(),
+        )
+        .to_string();
+
+        records.push(Record {
+            line,
+            entry,
+            raw: Line::Json(object),
+            key,
+        });
+    }
+
+    (records, errors)
+}
+
+fn report(errors: Vec<LineError>) -> Result<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+
+    for (line, error) in errors.iter().take(MAX_REPORTED_ERRORS) {
+        aeprintln!("Line {}: {}", line, error);
+    }
+    if errors.len() > MAX_REPORTED_ERRORS {
+        aeprintln!("... and {} more", errors.len() - MAX_REPORTED_ERRORS);
+    }
+
+    Err(eyre!("Invalid manifest: {} invalid line(s)", errors.len()))
+}
+
+/// Parses metadata given as space separated `key=value` pairs.
+pub fn parse_metadata(value: &str) -> Result<HashMap<String, String>> {
+    value
+        .split_whitespace()
+        .map(|pair| {
+            pair.split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .ok_or_else(|| eyre!("invalid metadata pair `{pair}`, expected key=value"))
+        })
+        .collect()
+}
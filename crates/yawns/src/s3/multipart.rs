@@ -0,0 +1,506 @@
+use crate::prelude::*;
+use crate::retry::{classify, RetryOptions};
+use aws_sdk_s3::types::{
+    CompletedMultipartUpload, CompletedPart, ObjectCannedAcl, ServerSideEncryption, StorageClass,
+};
+use aws_smithy_types::byte_stream::{ByteStream, Length};
+use futures::{StreamExt, TryStreamExt};
+
+use super::checksum::{self, ChecksumAlgorithm};
+use super::progress::{PartsProgress, Progress};
+use super::throttle::ThrottleOptions;
+
+/// Largest object that can be copied or uploaded with a single `CopyObject` or `PutObject` call.
+pub const MAX_SINGLE_OPERATION_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Smallest part size accepted by S3, except for the last part.
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+/// Max number of parts of a multipart upload.
+const MAX_PARTS: u64 = 10_000;
+
+#[derive(Debug, clap::Args, serde::Serialize, serde::Deserialize, Clone)]
+pub struct MultipartOptions {
+    /// Size of each part when an object has to be transferred with a multipart upload
+    /// (e.g. `64MiB`, `1GiB`.)
+    #[clap(long, env = "AWS_S3_PART_SIZE", default_value = "256MiB", value_parser = parse_byte_size)]
+    pub part_size: u64,
+    /// Max number of parts of a single object transferred in parallel.
+    #[clap(long, env = "AWS_S3_PART_CONCURRENCY", default_value = "8")]
+    pub part_concurrency: usize,
+    /// Send a checksum of this algorithm with every upload, part and copy, and fail the
+    /// transfer unless S3 returns the same checksum as the one computed locally.
+    #[clap(long, env = "AWS_S3_CHECKSUM_ALGORITHM", value_enum)]
+    pub checksum_algorithm: Option<ChecksumAlgorithm>,
+    #[clap(flatten)]
+    pub throttle: ThrottleOptions,
+    /// Progress of the bulk command the parts of every object count in.
+    #[clap(skip)]
+    #[serde(skip)]
+    pub progress: Option<Progress>,
+}
+
+impl MultipartOptions {
+    /// Counts the parts of an object of `size` bytes in the progress of the command, if any.
+    pub fn parts_progress(&self, size: u64) -> PartsProgress {
+        self.progress
+            .as_ref()
+            .map(|progress| progress.parts(size))
+            .unwrap_or_default()
+    }
+}
+
+/// Parses human friendly byte sizes such as `8MiB` or `5GB`.
+pub fn parse_byte_size(s: &str) -> std::result::Result<u64, String> {
+    s.parse::<bytesize::ByteSize>().map(|size| size.as_u64())
+}
+
+/// An inclusive byte range of an object that is transferred as a single part.
+#[derive(Debug, Clone, Copy)]
+pub struct Part {
+    pub number: i32,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Part {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value for the `Range` and `x-amz-copy-source-range` headers.
+    pub fn range(&self) -> String {
+        f!("bytes={}-{}", self.start, self.end)
+    }
+}
+
+/// Splits an object of `size` bytes into parts of roughly `part_size` bytes, growing the part
+/// size when needed to stay within the S3 limits.
+pub fn plan_parts(size: u64, part_size: u64) -> Vec<Part> {
+    let part_size = part_size
+        .max(MIN_PART_SIZE)
+        .max(size.div_ceil(MAX_PARTS))
+        .min(MAX_SINGLE_OPERATION_SIZE);
+
+    (0..size.div_ceil(part_size))
+        .map(|index| {
+            let start = index * part_size;
+            Part {
+                number: index as i32 + 1,
+                start,
+                end: (start + part_size).min(size) - 1,
+            }
+        })
+        .collect()
+}
+
+/// Settings of an object created by a copy or an upload. Unset fields are left to S3, or to
+/// the source object when copying.
+#[derive(Debug, Default, Clone)]
+pub struct ObjectAttributes {
+    pub metadata: Option<std::collections::HashMap<String, String>>,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_language: Option<String>,
+    pub cache_control: Option<String>,
+    /// Tags encoded as the `x-amz-tagging` header expects them.
+    pub tagging: Option<String>,
+    pub storage_class: Option<StorageClass>,
+    pub acl: Option<ObjectCannedAcl>,
+    pub server_side_encryption: Option<ServerSideEncryption>,
+    /// Id or ARN of the KMS key used with `aws:kms` encryption.
+    pub sse_kms_key_id: Option<String>,
+    pub bucket_key_enabled: Option<bool>,
+}
+
+impl ObjectAttributes {
+    /// Returns these attributes with every field set in `overrides` replaced.
+    pub fn merge(self, overrides: &ObjectAttributes) -> ObjectAttributes {
+        let overrides = overrides.clone();
+        ObjectAttributes {
+            metadata: overrides.metadata.or(self.metadata),
+            content_type: overrides.content_type.or(self.content_type),
+            content_encoding: overrides.content_encoding.or(self.content_encoding),
+            content_disposition: overrides.content_disposition.or(self.content_disposition),
+            content_language: overrides.content_language.or(self.content_language),
+            cache_control: overrides.cache_control.or(self.cache_control),
+            tagging: overrides.tagging.or(self.tagging),
+            storage_class: overrides.storage_class.or(self.storage_class),
+            acl: overrides.acl.or(self.acl),
+            server_side_encryption: overrides
+                .server_side_encryption
+                .or(self.server_side_encryption),
+            sse_kms_key_id: overrides.sse_kms_key_id.or(self.sse_kms_key_id),
+            bucket_key_enabled: overrides.bucket_key_enabled.or(self.bucket_key_enabled),
+        }
+    }
+
+    /// Whether any of the fields that `CopyObject` only changes when replacing the metadata of
+    /// the source object is set.
+    pub fn replaces_metadata(&self) -> bool {
+        self.metadata.is_some()
+            || self.content_type.is_some()
+            || self.content_encoding.is_some()
+            || self.content_disposition.is_some()
+            || self.content_language.is_some()
+            || self.cache_control.is_some()
+    }
+}
+
+/// Starts a multipart upload and returns its upload id.
+pub async fn create(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    attributes: &ObjectAttributes,
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+) -> Result<String> {
+    let response = client
+        .create_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .set_metadata(attributes.metadata.clone())
+        .set_content_type(attributes.content_type.clone())
+        .set_content_encoding(attributes.content_encoding.clone())
+        .set_content_disposition(attributes.content_disposition.clone())
+        .set_content_language(attributes.content_language.clone())
+        .set_cache_control(attributes.cache_control.clone())
+        .set_tagging(attributes.tagging.clone())
+        .set_storage_class(attributes.storage_class.clone())
+        .set_acl(attributes.acl.clone())
+        .set_server_side_encryption(attributes.server_side_encryption.clone())
+        .set_ssekms_key_id(attributes.sse_kms_key_id.clone())
+        .set_bucket_key_enabled(attributes.bucket_key_enabled)
+        .set_checksum_algorithm(checksum_algorithm.map(ChecksumAlgorithm::sdk))
+        .send()
+        .await
+        .map_err(|e| classify("S3 CreateMultipartUpload failed", e))?;
+
+    response
+        .upload_id
+        .ok_or_eyre("CreateMultipartUpload returned no upload id")
+}
+
+/// Completes a multipart upload from its parts, which can be given in any order.
+pub async fn complete(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    mut parts: Vec<CompletedPart>,
+) -> Result<aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadOutput> {
+    parts.sort_by_key(|part| part.part_number);
+
+    client
+        .complete_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build(),
+        )
+        .send()
+        .await
+        .map_err(|e| classify("S3 CompleteMultipartUpload failed", e))
+}
+
+/// Completes a multipart upload from its parts and the checksums computed for them, checking
+/// that S3 reports the same checksum for the whole object.
+async fn complete_verified(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    mut parts: Vec<(CompletedPart, Option<Vec<u8>>)>,
+    options: &MultipartOptions,
+    retry: &RetryOptions,
+) -> Result<aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadOutput> {
+    parts.sort_by_key(|(part, _)| part.part_number);
+    let (parts, checksums): (Vec<_>, Vec<_>) = parts.into_iter().unzip();
+
+    let parts = &parts;
+    let (response, _) = retry
+        .run(|| async move {
+            options.throttle.request().await;
+            complete(client, bucket, key, upload_id, parts.clone()).await
+        })
+        .await;
+    let response = response?;
+
+    if let Some(algorithm) = options.checksum_algorithm {
+        let checksums: Vec<_> = checksums.into_iter().flatten().collect();
+        checksum::verify(
+            algorithm,
+            &f!("{bucket}/{key}"),
+            &algorithm.composite(&checksums),
+            algorithm.pick(
+                response.checksum_crc32.as_deref(),
+                response.checksum_crc32_c.as_deref(),
+                response.checksum_sha1.as_deref(),
+                response.checksum_sha256.as_deref(),
+            ),
+        )?;
+    }
+
+    Ok(response)
+}
+
+/// Aborts a multipart upload so that its parts don't linger (and get billed) in the bucket.
+pub async fn abort(client: &aws_sdk_s3::Client, bucket: &str, key: &str, upload_id: &str) {
+    if let Err(err) = client
+        .abort_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .send()
+        .await
+    {
+        log::error!(
+            "Failed to abort multipart upload {} of {}/{}: {}",
+            upload_id,
+            bucket,
+            key,
+            err
+        );
+    }
+}
+
+/// Copies `source` (a `bucket/key` string) of `size` bytes into `bucket`/`key` using
+/// `UploadPartCopy`, copying up to `options.part_concurrency` parts at a time. Every request is
+/// retried as `retry` says, and the upload is aborted if any part still fails, or if the run
+/// gets cancelled. With a checksum algorithm, the
+/// checksum of the object has to match the one of the parts S3 reported while copying them.
+#[allow(clippy::too_many_arguments)]
+pub async fn copy(
+    client: &aws_sdk_s3::Client,
+    source: &str,
+    size: u64,
+    bucket: &str,
+    key: &str,
+    attributes: &ObjectAttributes,
+    options: &MultipartOptions,
+    retry: &RetryOptions,
+) -> Result<aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadOutput> {
+    // Every request is retried on its own, so that a part failing doesn't restart the whole
+    // object.
+    let client = &crate::aws::single_attempt(client);
+
+    let (upload_id, _) = retry
+        .run(|| async move {
+            options.throttle.request().await;
+            create(client, bucket, key, attributes, options.checksum_algorithm).await
+        })
+        .await;
+    let upload_id = upload_id?;
+
+    let progress = &options.parts_progress(size);
+    let parts = futures::stream::iter(plan_parts(size, options.part_size))
+        .map(|part| {
+            let upload_id = upload_id.as_str();
+            async move {
+                let (response, _) = retry
+                    .run(|| async move {
+                        options.throttle.request().await;
+                        client
+                            .upload_part_copy()
+                            .bucket(bucket)
+                            .key(key)
+                            .upload_id(upload_id)
+                            .copy_source(source)
+                            .copy_source_range(part.range())
+                            .part_number(part.number)
+                            .send()
+                            .await
+                            .map_err(|e| {
+                                classify(
+                                    &f!("S3 UploadPartCopy failed for part {}", part.number),
+                                    e,
+                                )
+                            })
+                    })
+                    .await;
+                let response = response?;
+
+                let result = response
+                    .copy_part_result
+                    .ok_or_eyre("UploadPartCopy returned no CopyPartResult")?;
+
+                let checksum = match options.checksum_algorithm {
+                    Some(algorithm) => {
+                        let returned = algorithm.pick(
+                            result.checksum_crc32.as_deref(),
+                            result.checksum_crc32_c.as_deref(),
+                            result.checksum_sha1.as_deref(),
+                            result.checksum_sha256.as_deref(),
+                        );
+                        let returned = returned.ok_or_else(|| {
+                            eyre!(
+                                "S3 returned no {} checksum for part {}",
+                                algorithm.sdk().as_str(),
+                                part.number
+                            )
+                        })?;
+                        Some(checksum::decode(returned)?)
+                    }
+                    None => None,
+                };
+
+                progress.done(part.len());
+                Ok((
+                    CompletedPart::builder()
+                        .part_number(part.number)
+                        .set_e_tag(result.e_tag)
+                        .set_checksum_crc32(result.checksum_crc32)
+                        .set_checksum_crc32_c(result.checksum_crc32_c)
+                        .set_checksum_sha1(result.checksum_sha1)
+                        .set_checksum_sha256(result.checksum_sha256)
+                        .build(),
+                    checksum,
+                )) as Result<(CompletedPart, Option<Vec<u8>>)>
+            }
+        })
+        .buffer_unordered(options.part_concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await;
+
+    let result = match parts {
+        Ok(parts) => {
+            complete_verified(client, bucket, key, &upload_id, parts, options, retry).await
+        }
+        Err(err) => Err(err),
+    };
+
+    if result.is_err() {
+        options.throttle.request().await;
+        abort(client, bucket, key, &upload_id).await;
+    }
+
+    result
+}
+
+/// Uploads the local file at `path` of `size` bytes into `bucket`/`key`, sending up to
+/// `options.part_concurrency` parts at a time. Each part is streamed straight from disk, so
+/// memory use doesn't grow with the file size. Every request is retried as `retry` says, and the
+/// upload is aborted if any part still fails, or if the run gets cancelled. With a checksum
+/// algorithm, every part and the whole object have to match the checksum computed while sending
+/// them.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload(
+    client: &aws_sdk_s3::Client,
+    path: &std::path::Path,
+    size: u64,
+    bucket: &str,
+    key: &str,
+    attributes: &ObjectAttributes,
+    options: &MultipartOptions,
+    retry: &RetryOptions,
+) -> Result<aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadOutput> {
+    // Every request is retried on its own, so that a part failing doesn't restart the whole
+    // object.
+    let client = &crate::aws::single_attempt(client);
+
+    let (upload_id, _) = retry
+        .run(|| async move {
+            options.throttle.request().await;
+            create(client, bucket, key, attributes, options.checksum_algorithm).await
+        })
+        .await;
+    let upload_id = upload_id?;
+
+    let progress = &options.parts_progress(size);
+    let parts = futures::stream::iter(plan_parts(size, options.part_size))
+        .map(|part| {
+            let upload_id = upload_id.as_str();
+            async move {
+                crate::cancel::check()?;
+                let (response, _) = retry
+                    .run(|| async move {
+                        let body = ByteStream::read_from()
+                            .path(path)
+                            .offset(part.start)
+                            .length(Length::Exact(part.len()))
+                            .build()
+                            .await
+                            .wrap_err_with(|| {
+                                f!("Failed to read part {} of {}", part.number, path.display())
+                            })?;
+                        let (body, checksum) = match options.checksum_algorithm {
+                            Some(algorithm) => {
+                                let (body, checksum) = algorithm.body(body);
+                                (body, Some(checksum))
+                            }
+                            None => (body, None),
+                        };
+
+                        options.throttle.request().await;
+                        let response = client
+                            .upload_part()
+                            .bucket(bucket)
+                            .key(key)
+                            .upload_id(upload_id)
+                            .part_number(part.number)
+                            .content_length(part.len() as i64)
+                            .set_checksum_algorithm(
+                                options.checksum_algorithm.map(ChecksumAlgorithm::sdk),
+                            )
+                            .body(options.throttle.body(body))
+                            .send()
+                            .await
+                            .map_err(|e| {
+                                classify(&f!("S3 UploadPart failed for part {}", part.number), e)
+                            })?;
+                        Ok((response, checksum))
+                    })
+                    .await;
+                let (response, checksum) = response?;
+
+                let checksum = checksum.map(|checksum| checksum.get()).transpose()?;
+                if let (Some(algorithm), Some(checksum)) = (options.checksum_algorithm, &checksum) {
+                    checksum::verify(
+                        algorithm,
+                        &f!("part {}", part.number),
+                        &checksum::encode(checksum),
+                        algorithm.pick(
+                            response.checksum_crc32.as_deref(),
+                            response.checksum_crc32_c.as_deref(),
+                            response.checksum_sha1.as_deref(),
+                            response.checksum_sha256.as_deref(),
+                        ),
+                    )?;
+                }
+
+                progress.done(part.len());
+                Ok((
+                    CompletedPart::builder()
+                        .part_number(part.number)
+                        .set_e_tag(response.e_tag)
+                        .set_checksum_crc32(response.checksum_crc32)
+                        .set_checksum_crc32_c(response.checksum_crc32_c)
+                        .set_checksum_sha1(response.checksum_sha1)
+                        .set_checksum_sha256(response.checksum_sha256)
+                        .build(),
+                    checksum,
+                )) as Result<(CompletedPart, Option<Vec<u8>>)>
+            }
+        })
+        .buffer_unordered(options.part_concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await;
+
+    let result = match parts {
+        Ok(parts) => {
+            complete_verified(client, bucket, key, &upload_id, parts, options, retry).await
+        }
+        Err(err) => Err(err),
+    };
+
+    if result.is_err() {
+        options.throttle.request().await;
+        abort(client, bucket, key, &upload_id).await;
+    }
+
+    result
+}
@@ -0,0 +1,276 @@
+use crate::prelude::*;
+use crate::retry::{classify, RetryOptions};
+use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+use aws_sdk_s3::types::ChecksumMode;
+use std::sync::Mutex;
+
+use super::copy::{copy_object, CopiedObject, ObjectCopy};
+use super::multipart::MultipartOptions;
+
+/// How a copy is checked before its source gets deleted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyMode {
+    /// The sizes have to match, and so do either a checksum or the ETags.
+    #[default]
+    Content,
+    /// Only the sizes have to match. Needed when ETags can't be compared, e.g. for objects
+    /// encrypted with SSE-KMS or uploaded with a different part size, and there's no checksum.
+    Size,
+}
+
+/// What proved that a copy matches its source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verification {
+    Checksum,
+    ETag,
+    Size,
+}
+
+impl std::fmt::Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verification::Checksum => write!(f, "checksum"),
+            Verification::ETag => write!(f, "etag"),
+            Verification::Size => write!(f, "size"),
+        }
+    }
+}
+
+/// Result of a successful move.
+#[derive(Debug, Clone)]
+pub struct MovedObject {
+    pub copied: CopiedObject,
+    pub verification: Verification,
+}
+
+/// What the attempts of a move learned about its source, shared between them.
+#[derive(Debug, Default)]
+pub struct Attempts {
+    /// The source as seen by the last attempt that found it.
+    source: Mutex<Option<HeadObjectOutput>>,
+}
+
+/// Copies an object, checks that the copy matches the source, and only then deletes the
+/// source. A source version that is given is the one deleted, leaving the others alone.
+///
+/// A source that is gone when a previous attempt found it was already moved by that attempt,
+/// as long as the destination still matches it.
+pub async fn move_object(
+    client: &aws_sdk_s3::Client,
+    copy: &ObjectCopy<'_>,
+    options: &MultipartOptions,
+    retry: &RetryOptions,
+    mode: VerifyMode,
+    attempts: &Attempts,
+) -> Result<MovedObject> {
+    check_locations(
+        (copy.source_bucket, copy.source_key),
+        (copy.destination_bucket, copy.destination_key),
+    )?;
+
+    options.throttle.request().await;
+    let source = match head_if_exists(
+        client,
+        copy.source_bucket,
+        copy.source_key,
+        copy.source_version_id,
+    )
+    .await?
+    {
+        Some(source) => source,
+        None => {
+            let previous = attempts.source.lock().unwrap().clone();
+            return match previous {
+                Some(source) => already_moved(client, copy, options, mode, &source).await,
+                None => Err(eyre!(
+                    "S3 HeadObject failed for {}/{}: the object doesn't exist",
+                    copy.source_bucket,
+                    copy.source_key
+                )),
+            };
+        }
+    };
+    *attempts.source.lock().unwrap() = Some(source.clone());
+
+    let copied = copy_object(client, copy, options, retry).await?;
+    options.throttle.request().await;
+    let destination = head(
+        client,
+        copy.destination_bucket,
+        copy.destination_key,
+        copied.version_id.as_deref(),
+    )
+    .await?;
+
+    let verification = verify(&source, &destination, mode).map_err(|e| {
+        eyre!(
+            "The copy of {}/{} to {}/{} doesn't match, the source was kept: {}",
+            copy.source_bucket,
+            copy.source_key,
+            copy.destination_bucket,
+            copy.destination_key,
+            e
+        )
+    })?;
+
+    options.throttle.request().await;
+    client
+        .delete_object()
+        .bucket(copy.source_bucket)
+        .key(copy.source_key)
+        .set_version_id(copy.source_version_id.map(String::from))
+        .send()
+        .await
+        .map_err(|e| {
+            classify(
+                &f!(
+                    "S3 DeleteObject failed for {}/{}",
+                    copy.source_bucket,
+                    copy.source_key
+                ),
+                e,
+            )
+        })?;
+
+    Ok(MovedObject {
+        copied,
+        verification,
+    })
+}
+
+/// Rejects a move onto its own source, which would delete the only copy of the object.
+pub fn check_locations(source: (&str, &str), destination: (&str, &str)) -> Result<()> {
+    if source == destination {
+        let (bucket, key) = source;
+        return Err(eyre!("Cannot move {bucket}/{key} onto itself"));
+    }
+
+    Ok(())
+}
+
+/// Outcome of a move whose source was deleted by a previous attempt, which only succeeds if
+/// the destination matches what that attempt saw of the source.
+async fn already_moved(
+    client: &aws_sdk_s3::Client,
+    copy: &ObjectCopy<'_>,
+    options: &MultipartOptions,
+    mode: VerifyMode,
+    source: &HeadObjectOutput,
+) -> Result<MovedObject> {
+    options.throttle.request().await;
+    let destination = head(client, copy.destination_bucket, copy.destination_key, None).await?;
+    let verification = verify(source, &destination, mode).map_err(|e| {
+        eyre!(
+            "The source {}/{} is gone and {}/{} doesn't match it: {}",
+            copy.source_bucket,
+            copy.source_key,
+            copy.destination_bucket,
+            copy.destination_key,
+            e
+        )
+    })?;
+    log::info!(
+        "{}/{} was already moved by a previous attempt",
+        copy.source_bucket,
+        copy.source_key
+    );
+
+    Ok(MovedObject {
+        copied: CopiedObject {
+            e_tag: destination.e_tag.unwrap_or_default(),
+            version_id: destination.version_id,
+            size: destination.content_length.unwrap_or_default().max(0) as u64,
+        },
+        verification,
+    })
+}
+
+/// Gets the attributes and checksums of an object, or nothing if it doesn't exist.
+async fn head_if_exists(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+) -> Result<Option<HeadObjectOutput>> {
+    match client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .set_version_id(version_id.map(String::from))
+        .checksum_mode(ChecksumMode::Enabled)
+        .send()
+        .await
+    {
+        Ok(head) => Ok(Some(head)),
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+        Err(e) => Err(classify(&f!("S3 HeadObject failed for {bucket}/{key}"), e)),
+    }
+}
+
+/// Gets the attributes and checksums of an object.
+pub async fn head(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+) -> Result<HeadObjectOutput> {
+    client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .set_version_id(version_id.map(String::from))
+        .checksum_mode(ChecksumMode::Enabled)
+        .send()
+        .await
+        .map_err(|e| classify(&f!("S3 HeadObject failed for {bucket}/{key}"), e))
+}
+
+/// Checks that `destination` holds the same content as `source`.
+fn verify(
+    source: &HeadObjectOutput,
+    destination: &HeadObjectOutput,
+    mode: VerifyMode,
+) -> Result<Verification> {
+    let source_size = source.content_length.unwrap_or_default();
+    let destination_size = destination.content_length.unwrap_or_default();
+    if source_size != destination_size {
+        return Err(eyre!(
+            "the source is {source_size} bytes but the destination is {destination_size}"
+        ));
+    }
+
+    if checksums(source)
+        .zip(checksums(destination))
+        .any(|(source, destination)| source.is_some() && source == destination)
+    {
+        return Ok(Verification::Checksum);
+    }
+
+    if source.e_tag.is_some() && source.e_tag == destination.e_tag {
+        return Ok(Verification::ETag);
+    }
+
+    match mode {
+        VerifyMode::Size => Ok(Verification::Size),
+        VerifyMode::Content => Err(eyre!(
+            "the ETags differ ({} and {}) and there's no matching checksum, use `--verify size` \
+             to only compare the sizes",
+            source.e_tag.as_deref().unwrap_or_default(),
+            destination.e_tag.as_deref().unwrap_or_default()
+        )),
+    }
+}
+
+/// Checksums of an object, one per algorithm, always in the same order.
+pub fn checksums(head: &HeadObjectOutput) -> impl Iterator<Item = Option<&str>> {
+    [
+        head.checksum_crc32.as_deref(),
+        head.checksum_crc32_c.as_deref(),
+        head.checksum_crc64_nvme.as_deref(),
+        head.checksum_sha1.as_deref(),
+        head.checksum_sha256.as_deref(),
+    ]
+    .into_iter()
+}
@@ -0,0 +1,472 @@
+use crate::output::Record;
+use crate::prelude::*;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::collections::VecDeque;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+
+use super::concurrency::Concurrency;
+use super::throttle::{RateLimit, ThrottleOptions};
+
+/// How often the progress of a bulk operation is reported when stderr isn't a terminal.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the progress bar gets refreshed.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Period the throughput and time remaining are averaged over.
+const RATE_WINDOW: Duration = Duration::from_secs(30);
+
+/// Parts of an item the share of it already transferred is counted in.
+const SHARE_SCALE: u64 = 1_000_000;
+
+#[derive(Debug, Default)]
+struct Counters {
+    completed: AtomicUsize,
+    failed: AtomicUsize,
+    cancelled: AtomicUsize,
+    in_flight: AtomicUsize,
+    retries: AtomicUsize,
+    bytes: AtomicU64,
+    /// Share of the items in flight already transferred, in [`SHARE_SCALE`]ths of an item.
+    partial: AtomicU64,
+}
+
+/// Shared progress of a bulk operation over `total` manifest lines.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    counters: Arc<Counters>,
+    total: usize,
+    start_time: Instant,
+    verb: &'static str,
+    /// Limits whose effective rate is reported along with the progress.
+    throttle: Option<ThrottleOptions>,
+    /// Adaptive limit whose current level is reported along with the progress.
+    concurrency: Option<Concurrency>,
+    /// Live progress bar, when stderr is a terminal.
+    bar: Option<ProgressBar>,
+}
+
+/// The task reporting the progress of a bulk operation.
+#[derive(Debug)]
+pub struct Reporter {
+    handle: JoinHandle<()>,
+    bar: Option<ProgressBar>,
+}
+
+impl Reporter {
+    /// Stops reporting, clearing the progress bar.
+    pub fn finish(self) {
+        self.handle.abort();
+        if let Some(bar) = self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+impl Progress {
+    /// Creates the progress tracker. `verb` describes what happens to each file in the
+    /// reports, e.g. `copied`.
+    pub fn new(total: usize, verb: &'static str) -> Self {
+        let bar = std::io::stderr().is_terminal().then(|| {
+            let bar =
+                ProgressBar::with_draw_target(Some(total as u64), ProgressDrawTarget::stderr());
+            bar.set_style(
+                ProgressStyle::with_template("{bar:40.cyan/blue} {pos}/{len} files {msg}")
+                    .expect("the progress bar template is valid")
+                    .progress_chars("=> "),
+            );
+            bar
+        });
+
+        Self {
+            counters: Arc::default(),
+            total,
+            start_time: Instant::now(),
+            verb,
+            throttle: None,
+            concurrency: None,
+            bar,
+        }
+    }
+
+    /// Reports the effective rate of the given limits along with the progress.
+    pub fn with_throttle(mut self, throttle: &ThrottleOptions) -> Self {
+        self.throttle = Some(throttle.clone());
+        self
+    }
+
+    /// Reports the current level of the given limit along with the progress, when it adapts.
+    pub fn with_concurrency(mut self, concurrency: &Concurrency) -> Self {
+        self.concurrency = concurrency.is_adaptive().then(|| concurrency.clone());
+        self
+    }
+
+    /// Counts an item as being processed, until it gets completed or failed.
+    pub fn started(&self) {
+        self.counters.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an item as done, along with the bytes it transferred.
+    pub fn completed(&self, bytes: u64) {
+        self.counters.completed.fetch_add(1, Ordering::Relaxed);
+        self.counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.finished();
+    }
+
+    pub fn failed(&self) {
+        self.counters.failed.fetch_add(1, Ordering::Relaxed);
+        self.finished();
+    }
+
+    /// Counts an item skipped or interrupted because the run was cancelled.
+    pub fn cancelled(&self) {
+        self.counters.cancelled.fetch_add(1, Ordering::Relaxed);
+        self.finished();
+    }
+
+    fn finished(&self) {
+        // Items of operations that don't report their start aren't counted in flight.
+        let _ = self.counters.in_flight.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |in_flight| in_flight.checked_sub(1),
+        );
+    }
+
+    /// Tracks an item of `size` bytes transferred in parts, until it gets completed or failed.
+    pub fn parts(&self, size: u64) -> PartsProgress {
+        PartsProgress {
+            counters: Some(self.counters.clone()),
+            size,
+            bytes: AtomicU64::default(),
+            share: AtomicU64::default(),
+        }
+    }
+
+    /// Prints a line without it getting mixed up with the progress bar.
+    pub fn println(&self, line: impl std::fmt::Display) {
+        match &self.bar {
+            Some(bar) => bar.suspend(|| aeprintln!("{}", line)),
+            None => aeprintln!("{}", line),
+        }
+    }
+
+    /// Adds the retries an item needed, given the number of attempts it took.
+    pub fn attempts(&self, attempts: u32) {
+        self.counters
+            .retries
+            .fetch_add(attempts.saturating_sub(1) as usize, Ordering::Relaxed);
+    }
+
+    /// Spawns a task that keeps a progress bar up to date, or reports the progress every few
+    /// seconds when stderr isn't a terminal, until it gets finished.
+    pub fn spawn_reporter(&self) -> Reporter {
+        let progress = self.clone();
+
+        let handle = tokio::spawn(async move {
+            let throttle = progress.throttle.clone().unwrap_or_default();
+            let mut bytes = Rate::new(throttle.max_bytes_per_sec);
+            let mut requests = Rate::new(throttle.max_requests_per_sec);
+            let mut window = Window::default();
+            window.rates(&progress.snapshot());
+            let mut reported_at = Instant::now();
+            let interval = match progress.bar {
+                Some(_) => REFRESH_INTERVAL,
+                None => REPORT_INTERVAL,
+            };
+
+            loop {
+                sleep(interval).await;
+
+                let snapshot = progress.snapshot();
+                let (files_per_second, bytes_per_second) = window.rates(&snapshot);
+                let time_remaining = if files_per_second > 0.0 {
+                    let remaining = (progress.total as f64 - snapshot.progressed()).max(0.0);
+                    Some(Duration::from_secs_f64(remaining / files_per_second))
+                } else {
+                    None
+                };
+                let time_remaining = time_remaining
+                    .map(|remaining| {
+                        humantime::format_duration(Duration::from_secs(remaining.as_secs()))
+                            .to_string()
+                    })
+                    .unwrap_or_else(|| "unknown".to_string());
+
+                let mut details = vec![f!(
+                    "{} ({}/s), {} in flight, {} failed, {} remaining",
+                    bytesize::ByteSize::b(snapshot.bytes),
+                    bytesize::ByteSize::b(bytes_per_second as u64),
+                    snapshot.in_flight,
+                    snapshot.failed,
+                    time_remaining
+                )];
+
+                // The limits are averaged over the previous report.
+                if reported_at.elapsed() >= REPORT_INTERVAL || progress.bar.is_none() {
+                    reported_at = Instant::now();
+                    bytes.next();
+                    requests.next();
+                }
+                if let Some((current, max)) = bytes.last() {
+                    details.push(f!(
+                        "throughput {}/s of {}/s max",
+                        bytesize::ByteSize::b(current as u64),
+                        bytesize::ByteSize::b(max as u64)
+                    ));
+                }
+                if let Some((current, max)) = requests.last() {
+                    details.push(f!("requests {:.2}/s of {:.2}/s max", current, max));
+                }
+                if let Some(concurrency) = &progress.concurrency {
+                    details.push(f!(
+                        "concurrency {} of {} max",
+                        concurrency.limit(),
+                        concurrency.max()
+                    ));
+                }
+
+                match &progress.bar {
+                    Some(bar) => {
+                        bar.set_position(snapshot.processed() as u64);
+                        bar.set_message(details.join(", "));
+                    }
+                    None => aeprintln!(
+                        "Progress: {}/{} files {} in {:.2} seconds ({:.2} files/second), {}",
+                        snapshot.completed,
+                        progress.total,
+                        progress.verb,
+                        progress.start_time.elapsed().as_secs_f64(),
+                        files_per_second,
+                        details.join(", ")
+                    ),
+                }
+            }
+        });
+
+        Reporter {
+            handle,
+            bar: self.bar.clone(),
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            at: Instant::now(),
+            completed: self.counters.completed.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            in_flight: self.counters.in_flight.load(Ordering::Relaxed),
+            bytes: self.counters.bytes.load(Ordering::Relaxed),
+            partial: self.counters.partial.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Final outcome of the operation.
+    pub fn summary(&self) -> Summary {
+        let snapshot = self.snapshot();
+        let duration = self.start_time.elapsed().as_secs_f64();
+
+        Summary {
+            total: self.total,
+            completed: snapshot.completed,
+            failed: snapshot.failed,
+            cancelled: self.counters.cancelled.load(Ordering::Relaxed),
+            retries: self.counters.retries.load(Ordering::Relaxed),
+            bytes: snapshot.bytes,
+            duration_seconds: duration,
+            files_per_second: snapshot.processed() as f64 / duration,
+        }
+    }
+}
+
+/// Counters at a point in time.
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    at: Instant,
+    completed: usize,
+    failed: usize,
+    in_flight: usize,
+    bytes: u64,
+    partial: u64,
+}
+
+impl Snapshot {
+    fn processed(&self) -> usize {
+        self.completed + self.failed
+    }
+
+    /// Items processed, counting the share of the items in flight already transferred.
+    fn progressed(&self) -> f64 {
+        self.processed() as f64 + self.partial as f64 / SHARE_SCALE as f64
+    }
+}
+
+/// An item transferred in parts. The bytes of each part count as soon as the part is done, so
+/// that the throughput and the time remaining move while a large object is transferred. They
+/// are taken back once the item is dropped, as completing it counts all its bytes.
+#[derive(Debug, Default)]
+pub struct PartsProgress {
+    counters: Option<Arc<Counters>>,
+    size: u64,
+    bytes: AtomicU64,
+    share: AtomicU64,
+}
+
+impl PartsProgress {
+    /// Counts a part of `bytes` bytes as transferred.
+    pub fn done(&self, bytes: u64) {
+        let Some(counters) = &self.counters else {
+            return;
+        };
+        let share = (bytes as u128 * SHARE_SCALE as u128 / self.size.max(1) as u128) as u64;
+
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.share.fetch_add(share, Ordering::Relaxed);
+        counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+        counters.partial.fetch_add(share, Ordering::Relaxed);
+    }
+}
+
+impl Drop for PartsProgress {
+    fn drop(&mut self) {
+        if let Some(counters) = &self.counters {
+            counters
+                .bytes
+                .fetch_sub(*self.bytes.get_mut(), Ordering::Relaxed);
+            counters
+                .partial
+                .fetch_sub(*self.share.get_mut(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Recent snapshots, to work out moving averages that follow changes of pace.
+#[derive(Debug, Default)]
+struct Window {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl Window {
+    /// Files and bytes per second over the window ending with `snapshot`.
+    fn rates(&mut self, snapshot: &Snapshot) -> (f64, f64) {
+        while self
+            .snapshots
+            .front()
+            .is_some_and(|oldest| snapshot.at.duration_since(oldest.at) > RATE_WINDOW)
+        {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(*snapshot);
+
+        let oldest = self.snapshots.front().unwrap_or(snapshot);
+        let elapsed = snapshot.at.duration_since(oldest.at).as_secs_f64();
+        if elapsed <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        (
+            (snapshot.progressed() - oldest.progressed()).max(0.0) / elapsed,
+            snapshot.bytes.saturating_sub(oldest.bytes) as f64 / elapsed,
+        )
+    }
+}
+
+/// Effective rate of a limit between two reports.
+struct Rate {
+    limit: Option<RateLimit>,
+    taken: u64,
+    at: Instant,
+    last: Option<(f64, f64)>,
+}
+
+impl Rate {
+    fn new(limit: Option<RateLimit>) -> Self {
+        let taken = limit.as_ref().map(RateLimit::taken).unwrap_or_default();
+        Self {
+            limit,
+            taken,
+            at: Instant::now(),
+            last: None,
+        }
+    }
+
+    /// Rate since the previous call, and the max rate of the limit, if there is a limit.
+    fn next(&mut self) -> Option<(f64, f64)> {
+        let limit = self.limit.as_ref()?;
+        let taken = limit.taken();
+        let elapsed = self.at.elapsed().as_secs_f64();
+        let current = (taken - self.taken) as f64 / elapsed.max(f64::EPSILON);
+
+        self.taken = taken;
+        self.at = Instant::now();
+        self.last = Some((current, limit.rate()));
+
+        self.last
+    }
+
+    /// Result of the previous call to [`Rate::next`].
+    fn last(&self) -> Option<(f64, f64)> {
+        self.last
+    }
+}
+
+/// Outcome of a bulk operation.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Summary {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub retries: usize,
+    pub bytes: u64,
+    pub duration_seconds: f64,
+    pub files_per_second: f64,
+}
+
+impl Summary {
+    /// Fails when any file failed or was cancelled, so that the command exits with an error.
+    pub fn ensure_success(&self) -> Result<()> {
+        if self.failed > 0 {
+            Err(eyre!("{} of {} file(s) failed.", self.failed, self.total))
+        } else if self.cancelled > 0 {
+            Err(eyre!(
+                "Cancelled, {} of {} file(s) were not processed.",
+                self.cancelled,
+                self.total
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Record for Summary {
+    const COLUMNS: &'static [&'static str] = &[
+        "Total",
+        "Completed",
+        "Failed",
+        "Cancelled",
+        "Retries",
+        "Bytes",
+        "Seconds",
+        "Files/Second",
+    ];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.total.to_string(),
+            self.completed.to_string(),
+            self.failed.to_string(),
+            self.cancelled.to_string(),
+            self.retries.to_string(),
+            bytesize::ByteSize::b(self.bytes).to_string(),
+            f!("{:.2}", self.duration_seconds),
+            f!("{:.2}", self.files_per_second),
+        ]
+    }
+}
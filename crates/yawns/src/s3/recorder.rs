@@ -0,0 +1,108 @@
+use crate::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::failed::FailedOutput;
+use super::journal::{self, Journal};
+use super::manifest::{ManifestOptions, Record, Schema};
+
+/// Keeps track of where the outcome of each manifest line of a bulk operation gets recorded.
+#[derive(Debug, Default, Clone)]
+pub struct Recorder {
+    journal: Option<Arc<Journal>>,
+    failed_output: Option<Arc<FailedOutput>>,
+}
+
+impl Recorder {
+    /// Opens the progress journal and creates the failed-items file, when requested.
+    pub async fn new(
+        journal: Option<&Path>,
+        failed_output: Option<&Path>,
+        schema: Schema,
+        manifest: &ManifestOptions,
+    ) -> Result<Self> {
+        let journal = match journal {
+            Some(path) => {
+                let journal = Journal::open(path).await?;
+                aeprintln!(
+                    "Resuming from journal {}: {} lines completed, {} failed",
+                    journal.path().display(),
+                    journal.count(journal::Status::Completed),
+                    journal.count(journal::Status::Failed)
+                );
+                Some(Arc::new(journal))
+            }
+            None => None,
+        };
+
+        let failed_output = match failed_output {
+            Some(path) => Some(Arc::new(
+                FailedOutput::create(path, schema, manifest).await?,
+            )),
+            None => None,
+        };
+
+        Ok(Self {
+            journal,
+            failed_output,
+        })
+    }
+
+    /// Whether the record was already completed by a previous run.
+    pub fn is_completed(&self, record: &Record) -> bool {
+        self.journal
+            .as_ref()
+            .is_some_and(|j| j.is_completed(record.key()))
+    }
+
+    /// Records a successfully processed record.
+    pub async fn completed(&self, record: &Record) {
+        if let Some(journal) = self.journal.as_deref() {
+            if let Err(err) = journal.record_completed(record.key()).await {
+                log::error!(
+                    "Failed to write to journal {}: {}",
+                    journal.path().display(),
+                    err
+                );
+            }
+        }
+    }
+
+    /// Records a successfully processed record, and how its result was verified.
+    pub async fn verified(&self, record: &Record, verification: impl std::fmt::Display) {
+        if let Some(journal) = self.journal.as_deref() {
+            if let Err(err) = journal.record_verified(record.key(), verification).await {
+                log::error!(
+                    "Failed to write to journal {}: {}",
+                    journal.path().display(),
+                    err
+                );
+            }
+        }
+    }
+
+    /// Records a record that failed with the given error.
+    pub async fn failed(&self, record: &Record, error: impl std::fmt::Display) {
+        let error = error.to_string();
+
+        if let Some(journal) = self.journal.as_deref() {
+            if let Err(err) = journal.record_failed(record.key(), &error).await {
+                log::error!(
+                    "Failed to write to journal {}: {}",
+                    journal.path().display(),
+                    err
+                );
+            }
+        }
+
+        if let Some(failed_output) = self.failed_output.as_deref() {
+            if let Err(err) = failed_output.write(record, &error).await {
+                log::error!(
+                    "Failed to write to failed-output file {}: {}",
+                    failed_output.path().display(),
+                    err
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use super::mv::{self, VerifyMode};
+use super::progress::Summary;
+
+/// What happened to an item of a bulk operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Outcome of a single item, as written to the report.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Item {
+    pub source: String,
+    pub destination: String,
+    pub status: Status,
+    pub e_tag: Option<String>,
+    pub version_id: Option<String>,
+    pub bytes: u64,
+    pub attempts: u32,
+    pub duration_seconds: f64,
+    pub error: Option<String>,
+    /// How the copy was checked before deleting the source, for the moves.
+    pub verification: Option<Verification>,
+}
+
+/// How the copy of a moved object was checked.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Verification {
+    pub mode: VerifyMode,
+    /// What proved that the copy matches its source, missing when the move failed.
+    pub result: Option<mv::Verification>,
+}
+
+impl Item {
+    /// An item that started at `start` and ended with `result`, which holds the ETag, version
+    /// id and size of the written object when it succeeded.
+    pub fn new<T>(
+        source: String,
+        destination: String,
+        start: Instant,
+        attempts: u32,
+        result: &Result<T>,
+        written: impl FnOnce(&T) -> (Option<String>, Option<String>, u64),
+    ) -> Self {
+        let (status, (e_tag, version_id, bytes), error) = match result {
+            Ok(value) => (Status::Completed, written(value), None),
+            Err(e) => {
+                let status = if crate::cancel::is_cancelled(e) {
+                    Status::Cancelled
+                } else {
+                    Status::Failed
+                };
+                (status, (None, None, 0), Some(e.to_string()))
+            }
+        };
+
+        Self {
+            source,
+            destination,
+            status,
+            e_tag,
+            version_id,
+            bytes,
+            attempts,
+            duration_seconds: start.elapsed().as_secs_f64(),
+            error,
+            verification: None,
+        }
+    }
+
+    /// Records how the copy of a moved object was checked.
+    pub fn with_verification(mut self, mode: VerifyMode, result: Option<mv::Verification>) -> Self {
+        self.verification = Some(Verification { mode, result });
+        self
+    }
+}
+
+/// The document written by `--report`.
+#[derive(Debug, serde::Serialize)]
+struct Document<'a> {
+    command: &'static str,
+    started_at: String,
+    finished_at: String,
+    duration_seconds: f64,
+    /// Totals of the run, missing when it failed before processing any item.
+    summary: Option<&'a Summary>,
+    bytes_per_second: f64,
+    /// Error that ended the run, if any.
+    error: Option<String>,
+    items: &'a [Item],
+}
+
+/// Collects the outcome of every item of a bulk operation, to write them as a JSON report once
+/// the operation is over. Does nothing without a path. Clones share the same items.
+#[derive(Debug, Clone)]
+pub struct Report {
+    path: Option<PathBuf>,
+    command: &'static str,
+    started_at: SystemTime,
+    items: Arc<Mutex<Vec<Item>>>,
+}
+
+impl Report {
+    pub fn new(path: Option<&Path>, command: &'static str) -> Self {
+        Self {
+            path: path.map(Path::to_path_buf),
+            command,
+            started_at: SystemTime::now(),
+            items: Arc::default(),
+        }
+    }
+
+    /// Adds the outcome of an item.
+    pub fn item(&self, item: impl FnOnce() -> Item) {
+        if self.path.is_some() {
+            self.items.lock().unwrap().push(item());
+        }
+    }
+
+    /// Writes the report of a run that ended with `result`, whether it succeeded or not. Failing
+    /// to write it only gets logged, so that it doesn't hide how the run went.
+    pub async fn write(&self, result: &Result<Summary>) {
+        if let Err(e) = self.save(result).await {
+            aeprintln!("{:#}", e);
+        }
+    }
+
+    async fn save(&self, result: &Result<Summary>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let finished_at = SystemTime::now();
+        let duration = finished_at
+            .duration_since(self.started_at)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let items = std::mem::take(&mut *self.items.lock().unwrap());
+        let summary = result.as_ref().ok();
+        let error = match result {
+            Ok(summary) => summary.ensure_success().err().map(|e| e.to_string()),
+            Err(e) => Some(e.to_string()),
+        };
+
+        let document = Document {
+            command: self.command,
+            started_at: humantime::format_rfc3339_millis(self.started_at).to_string(),
+            finished_at: humantime::format_rfc3339_millis(finished_at).to_string(),
+            duration_seconds: duration,
+            summary,
+            bytes_per_second: summary
+                .map(|summary| summary.bytes as f64 / duration)
+                .unwrap_or_default(),
+            error,
+            items: &items,
+        };
+
+        tokio::fs::write(path, serde_json::to_vec_pretty(&document)?)
+            .await
+            .wrap_err_with(|| f!("Failed to write the report to {}", path.display()))
+    }
+}
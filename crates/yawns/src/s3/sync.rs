@@ -0,0 +1,441 @@
+use crate::output::Record;
+use crate::prelude::*;
+use crate::retry::RetryOptions;
+use futures::{StreamExt, TryStreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::copy::{copy_object, ObjectCopy};
+use super::download::{download_object, ObjectDownload};
+use super::listing::{list_objects, ListingOptions};
+use super::multipart::{MultipartOptions, ObjectAttributes};
+use super::upload::{upload_object, ContentTypeDetection, ObjectUpload};
+
+/// One side of a sync: a local directory or an S3 prefix given as `s3://bucket/prefix`.
+#[derive(Debug, Clone)]
+pub enum Location {
+    Local(PathBuf),
+    S3 { bucket: String, prefix: String },
+}
+
+impl std::str::FromStr for Location {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let Some(path) = s.strip_prefix("s3://") else {
+            return Ok(Location::Local(PathBuf::from(s)));
+        };
+
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        if bucket.is_empty() {
+            return Err(f!("invalid S3 location `{s}`: no bucket found"));
+        }
+
+        // Prefixes are treated as directories, so `s3://bucket/a` doesn't match `ab/c`.
+        let prefix = if prefix.is_empty() || prefix.ends_with('/') {
+            prefix.to_string()
+        } else {
+            f!("{prefix}/")
+        };
+
+        Ok(Location::S3 {
+            bucket: bucket.to_string(),
+            prefix,
+        })
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Local(path) => write!(f, "{}", path.display()),
+            Location::S3 { bucket, prefix } => write!(f, "s3://{bucket}/{prefix}"),
+        }
+    }
+}
+
+impl Location {
+    /// Displayable path of the entry at `key`, relative to this location.
+    fn join(&self, key: &str) -> String {
+        match self {
+            Location::Local(path) => path.join(key).display().to_string(),
+            Location::S3 { bucket, prefix } => f!("s3://{bucket}/{prefix}{key}"),
+        }
+    }
+}
+
+/// How to decide whether an entry that exists on both sides has to be transferred again.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CompareMode {
+    /// Transfer when the size differs or the source is newer than the destination.
+    #[default]
+    SizeAndMtime,
+    /// Transfer only when the size differs.
+    Size,
+    /// Transfer when the size or the ETag (the MD5 of local files) differs. Falls back to
+    /// `size-and-mtime` for multipart ETags, which can't be computed locally.
+    Etag,
+}
+
+/// A file or object found on one side of the sync.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub size: u64,
+    /// Last modification time, in seconds since the Unix epoch.
+    pub modified: Option<i64>,
+    pub e_tag: Option<String>,
+}
+
+/// Include and exclude glob patterns matched against the relative key of every entry.
+#[derive(Debug)]
+pub struct Filter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: glob_set(include)?,
+            exclude: glob_set(exclude)?,
+        })
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        self.include.as_ref().is_none_or(|set| set.is_match(key))
+            && !self.exclude.as_ref().is_some_and(|set| set.is_match(key))
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).wrap_err_with(|| f!("Invalid glob `{pattern}`"))?);
+    }
+
+    Ok(Some(builder.build()?))
+}
+
+/// A single step of the sync plan, identified by the key relative to both locations.
+#[derive(Debug, Clone)]
+pub enum Action {
+    Transfer(String),
+    Delete(String),
+}
+
+impl Action {
+    pub fn describe(&self, source: &Location, destination: &Location) -> String {
+        let planned = self.planned(source, destination);
+        match planned.source {
+            Some(from) => f!("{}: {} to {}", planned.action, from, planned.destination),
+            None => f!("{}: {}", planned.action, planned.destination),
+        }
+    }
+
+    pub fn planned(&self, source: &Location, destination: &Location) -> PlannedAction {
+        match self {
+            Action::Transfer(key) => PlannedAction {
+                action: match (source, destination) {
+                    (Location::Local(_), _) => "upload",
+                    (_, Location::Local(_)) => "download",
+                    _ => "copy",
+                },
+                source: Some(source.join(key)),
+                destination: destination.join(key),
+            },
+            Action::Delete(key) => PlannedAction {
+                action: "delete",
+                source: None,
+                destination: destination.join(key),
+            },
+        }
+    }
+}
+
+/// A step of the sync plan, as printed by a dry run.
+#[derive(Debug, serde::Serialize)]
+pub struct PlannedAction {
+    pub action: &'static str,
+    pub source: Option<String>,
+    pub destination: String,
+}
+
+impl Record for PlannedAction {
+    const COLUMNS: &'static [&'static str] = &["Action", "Source", "Destination"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.action.to_string(),
+            self.source.clone().unwrap_or_default(),
+            self.destination.clone(),
+        ]
+    }
+}
+
+/// Lists every entry under `location`, keyed by its path relative to it.
+pub async fn list(
+    client: &aws_sdk_s3::Client,
+    location: &Location,
+    listing: &ListingOptions,
+) -> Result<BTreeMap<String, Entry>> {
+    match location {
+        Location::S3 { bucket, prefix } => {
+            let objects = list_objects(client, bucket, Some(prefix), listing).await?;
+
+            Ok(objects
+                .into_iter()
+                .filter_map(|object| {
+                    let key = object.key?.strip_prefix(prefix.as_str())?.to_string();
+                    // Skip the empty "folder" objects created by the console.
+                    if key.is_empty() || key.ends_with('/') {
+                        return None;
+                    }
+                    let entry = Entry {
+                        size: object.size.unwrap_or_default().max(0) as u64,
+                        modified: object.last_modified.map(|date| date.secs()),
+                        e_tag: object.e_tag,
+                    };
+                    Some((key, entry))
+                })
+                .collect())
+        }
+        Location::Local(root) => {
+            let root = root.clone();
+            tokio::task::spawn_blocking(move || list_local(&root)).await?
+        }
+    }
+}
+
+fn list_local(root: &Path) -> Result<BTreeMap<String, Entry>> {
+    let mut entries = BTreeMap::new();
+
+    if !root.exists() {
+        return Ok(entries);
+    }
+
+    for entry in walkdir::WalkDir::new(root).follow_links(true) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        let key = entry
+            .path()
+            .strip_prefix(root)?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        entries.insert(
+            key,
+            Entry {
+                size: metadata.len(),
+                modified: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|duration| duration.as_secs() as i64),
+                e_tag: None,
+            },
+        );
+    }
+
+    Ok(entries)
+}
+
+/// Works out what has to be transferred, and deleted if `delete` is set, to make the
+/// destination look like the source. Up to `max_concurrent` entries are compared at a time.
+#[allow(clippy::too_many_arguments)]
+pub async fn plan(
+    source: &Location,
+    source_entries: &BTreeMap<String, Entry>,
+    destination: &Location,
+    destination_entries: &BTreeMap<String, Entry>,
+    compare: CompareMode,
+    delete: bool,
+    filter: &Filter,
+    max_concurrent: usize,
+) -> Result<Vec<Action>> {
+    let mut transfers: Vec<String> =
+        futures::stream::iter(source_entries.iter().filter(|(key, _)| filter.matches(key)))
+            .map(|(key, entry)| async move {
+                let transfer = match destination_entries.get(key) {
+                    None => true,
+                    Some(existing) => {
+                        differs(source, destination, key, entry, existing, compare).await?
+                    }
+                };
+                Ok(transfer.then(|| key.clone())) as Result<Option<String>>
+            })
+            .buffer_unordered(max_concurrent.max(1))
+            .try_filter_map(|key| async move { Ok(key) })
+            .try_collect()
+            .await?;
+
+    // Comparisons finish in any order.
+    transfers.sort();
+    let mut actions: Vec<_> = transfers.into_iter().map(Action::Transfer).collect();
+
+    if delete {
+        actions.extend(
+            destination_entries
+                .keys()
+                .filter(|key| filter.matches(key) && !source_entries.contains_key(*key))
+                .map(|key| Action::Delete(key.clone())),
+        );
+    }
+
+    Ok(actions)
+}
+
+async fn differs(
+    source: &Location,
+    destination: &Location,
+    key: &str,
+    entry: &Entry,
+    existing: &Entry,
+    compare: CompareMode,
+) -> Result<bool> {
+    if entry.size != existing.size {
+        return Ok(true);
+    }
+
+    let newer = entry.modified > existing.modified;
+
+    match compare {
+        CompareMode::Size => Ok(false),
+        CompareMode::SizeAndMtime => Ok(newer),
+        CompareMode::Etag => {
+            let source_etag = e_tag(source, key, entry).await?;
+            let destination_etag = e_tag(destination, key, existing).await?;
+
+            match (source_etag, destination_etag) {
+                (Some(source_etag), Some(destination_etag)) => Ok(source_etag != destination_etag),
+                _ => Ok(newer),
+            }
+        }
+    }
+}
+
+/// Unquoted ETag of an entry, computing the MD5 of local files. Returns `None` for multipart
+/// ETags when the other side is a local file, as those can't be reproduced.
+async fn e_tag(location: &Location, key: &str, entry: &Entry) -> Result<Option<String>> {
+    match location {
+        Location::S3 { .. } => Ok(entry
+            .e_tag
+            .as_deref()
+            .map(|e_tag| e_tag.trim_matches('"').to_string())
+            .filter(|e_tag| !e_tag.contains('-'))),
+        Location::Local(root) => {
+            let path = root.join(key);
+            let digest = tokio::task::spawn_blocking(move || md5_file(&path)).await??;
+            Ok(Some(digest))
+        }
+    }
+}
+
+fn md5_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0; 1024 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        context.consume(&buffer[..read]);
+    }
+
+    Ok(f!("{:x}", context.compute()))
+}
+
+/// Carries out a single action of the plan. Objects written to S3 get the given `attributes`,
+/// and uploaded files a Content-Type detected as `content_type_detection` says.
+///
+/// Returns the number of bytes transferred.
+#[allow(clippy::too_many_arguments)]
+pub async fn apply(
+    client: &aws_sdk_s3::Client,
+    action: &Action,
+    source: &Location,
+    destination: &Location,
+    threshold: u64,
+    multipart: &MultipartOptions,
+    retry: &RetryOptions,
+    attributes: &ObjectAttributes,
+    content_type_detection: ContentTypeDetection,
+) -> Result<u64> {
+    let size = match (action, source, destination) {
+        (Action::Transfer(key), Location::Local(root), Location::S3 { bucket, prefix }) => {
+            let upload = ObjectUpload {
+                path: &root.join(key),
+                bucket,
+                key: &f!("{prefix}{key}"),
+                attributes: attributes.clone(),
+                content_type_detection,
+            };
+            upload_object(client, &upload, threshold, multipart, retry)
+                .await?
+                .size
+        }
+        (Action::Transfer(key), Location::S3 { bucket, prefix }, Location::Local(root)) => {
+            let download = ObjectDownload {
+                bucket,
+                key: &f!("{prefix}{key}"),
+                version_id: None,
+                destination: &root.join(key),
+            };
+            download_object(client, &download, threshold, multipart, retry).await?
+        }
+        (
+            Action::Transfer(key),
+            Location::S3 {
+                bucket: source_bucket,
+                prefix: source_prefix,
+            },
+            Location::S3 { bucket, prefix },
+        ) => {
+            let copy = ObjectCopy {
+                source_bucket,
+                source_key: &f!("{source_prefix}{key}"),
+                source_version_id: None,
+                destination_bucket: bucket,
+                destination_key: &f!("{prefix}{key}"),
+                attributes: attributes.clone(),
+            };
+            copy_object(client, &copy, multipart, retry).await?.size
+        }
+        (Action::Delete(key), _, Location::S3 { bucket, prefix }) => {
+            client
+                .delete_object()
+                .bucket(bucket)
+                .key(f!("{prefix}{key}"))
+                .send()
+                .await
+                .map_err(|e| crate::retry::classify("S3 DeleteObject failed", e))?;
+            0
+        }
+        (Action::Delete(key), _, Location::Local(root)) => {
+            let path = root.join(key);
+            tokio::fs::remove_file(&path)
+                .await
+                .wrap_err_with(|| f!("Failed to delete {}", path.display()))?;
+            0
+        }
+        (Action::Transfer(_), Location::Local(_), Location::Local(_)) => {
+            return Err(eyre!("Syncing two local directories is not supported"));
+        }
+    };
+
+    Ok(size)
+}
@@ -0,0 +1,214 @@
+use aws_smithy_types::body::SdkBody;
+use aws_smithy_types::byte_stream::ByteStream;
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+use tokio::time::{sleep, Duration};
+
+use super::multipart::parse_byte_size;
+
+#[derive(Debug, Default, clap::Args, serde::Serialize, serde::Deserialize, Clone)]
+pub struct ThrottleOptions {
+    /// Max number of bytes uploaded and downloaded per second, shared by all the transfers of
+    /// the command (e.g. `20MiB`.)
+    #[clap(long, env = "AWS_S3_MAX_BYTES_PER_SEC", value_parser = parse_byte_rate)]
+    pub max_bytes_per_sec: Option<RateLimit>,
+    /// Max number of S3 requests sent per second, shared by all the transfers of the command.
+    #[clap(long, env = "AWS_S3_MAX_REQUESTS_PER_SEC", value_parser = parse_request_rate)]
+    pub max_requests_per_sec: Option<RateLimit>,
+}
+
+impl ThrottleOptions {
+    /// Waits until another request can be sent.
+    pub async fn request(&self) {
+        if let Some(limit) = &self.max_requests_per_sec {
+            limit.acquire(1).await;
+        }
+    }
+
+    /// Waits until `bytes` more bytes can be transferred.
+    pub async fn bytes(&self, bytes: u64) {
+        if let Some(limit) = &self.max_bytes_per_sec {
+            limit.acquire(bytes).await;
+        }
+    }
+
+    /// Makes an upload body go no faster than the throttle allows, taking the tokens of every
+    /// chunk as it gets sent rather than the whole body up front.
+    pub fn body(&self, body: ByteStream) -> ByteStream {
+        match &self.max_bytes_per_sec {
+            Some(limit) => {
+                let limit = limit.clone();
+                body.map(move |body| {
+                    SdkBody::from_body_1_x(ThrottledBody {
+                        inner: body,
+                        limit: limit.clone(),
+                        pending: None,
+                    })
+                })
+            }
+            None => body,
+        }
+    }
+}
+
+/// Waits for the tokens of a chunk.
+type Acquire = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+
+/// A request body handing out each chunk once its tokens are taken.
+struct ThrottledBody {
+    inner: SdkBody,
+    limit: RateLimit,
+    /// Chunk read from `inner`, waiting for its tokens.
+    pending: Option<(Bytes, Acquire)>,
+}
+
+impl Body for ThrottledBody {
+    type Data = Bytes;
+    type Error = aws_smithy_types::body::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Self::Error>>> {
+        loop {
+            if let Some((_, acquired)) = &mut self.pending {
+                ready!(acquired.as_mut().poll(cx));
+                let (bytes, _) = self.pending.take().unwrap();
+                return Poll::Ready(Some(Ok(Frame::data(bytes))));
+            }
+
+            match ready!(Pin::new(&mut self.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(bytes) => {
+                        let limit = self.limit.clone();
+                        let amount = bytes.len() as u64;
+                        self.pending =
+                            Some((bytes, Box::pin(async move { limit.acquire(amount).await })));
+                    }
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                frame => return Poll::Ready(frame),
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let pending = self
+            .pending
+            .as_ref()
+            .map_or(0, |(bytes, _)| bytes.len() as u64);
+        let inner = Body::size_hint(&self.inner);
+
+        let mut hint = SizeHint::new();
+        hint.set_lower(inner.lower() + pending);
+        if let Some(upper) = inner.upper() {
+            hint.set_upper(upper + pending);
+        }
+        hint
+    }
+}
+
+fn parse_byte_rate(s: &str) -> std::result::Result<RateLimit, String> {
+    match parse_byte_size(s)? {
+        0 => Err("the rate has to be greater than zero".to_string()),
+        rate => Ok(RateLimit::new(rate as f64)),
+    }
+}
+
+fn parse_request_rate(s: &str) -> std::result::Result<RateLimit, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(RateLimit::new(rate)),
+        Ok(_) => Err("the rate has to be greater than zero".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// A token bucket refilled at `rate` tokens per second, holding up to a second worth of them.
+/// Clones share the same bucket.
+///
+/// Taking more tokens than available is allowed, and makes the caller wait until the bucket
+/// is back in the black, so a large part only has to wait once rather than never fitting.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    rate: f64,
+    bucket: Arc<Mutex<Bucket>>,
+    /// Tokens handed out since the limit was created, to work out the effective rate.
+    taken: Arc<AtomicU64>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimit {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: rate,
+                refilled_at: Instant::now(),
+            })),
+            taken: Arc::default(),
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Number of tokens taken so far.
+    pub fn taken(&self) -> u64 {
+        self.taken.load(Ordering::Relaxed)
+    }
+
+    /// Takes `amount` tokens, waiting for the bucket to refill when it runs out.
+    pub async fn acquire(&self, amount: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.rate;
+            bucket.tokens = (bucket.tokens + refill).min(self.rate) - amount as f64;
+            bucket.refilled_at = now;
+
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+        self.taken.fetch_add(amount, Ordering::Relaxed);
+    }
+}
+
+impl serde::Serialize for RateLimit {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.rate)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for RateLimit {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        f64::deserialize(deserializer).map(RateLimit::new)
+    }
+}
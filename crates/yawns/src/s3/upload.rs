@@ -0,0 +1,207 @@
+use crate::prelude::*;
+use crate::retry::{classify, RetryOptions};
+use aws_smithy_types::byte_stream::ByteStream;
+use std::io::Read;
+use std::path::Path;
+
+use super::checksum::{self, ChecksumAlgorithm};
+use super::multipart::{self, MultipartOptions, ObjectAttributes, MAX_SINGLE_OPERATION_SIZE};
+
+/// Number of leading bytes of a file looked at to recognize its type.
+const SNIFF_SIZE: u64 = 8 * 1024;
+
+/// How the Content-Type of an uploaded file is worked out when none is given.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ContentTypeDetection {
+    /// Guess it from the file extension.
+    #[default]
+    Extension,
+    /// Guess it from the file extension, and from the first bytes of the file when the
+    /// extension is missing or unknown.
+    Sniff,
+    /// Leave it to S3.
+    Off,
+}
+
+/// A single local file upload.
+#[derive(Debug, Clone)]
+pub struct ObjectUpload<'a> {
+    pub path: &'a Path,
+    pub bucket: &'a str,
+    pub key: &'a str,
+    /// Attributes of the uploaded object. The Content-Type is detected when unset.
+    pub attributes: ObjectAttributes,
+    pub content_type_detection: ContentTypeDetection,
+}
+
+/// Result of a successful upload.
+#[derive(Debug, Clone)]
+pub struct UploadedObject {
+    pub e_tag: Option<String>,
+    pub version_id: Option<String>,
+    pub size: u64,
+}
+
+/// Uploads a local file, using a multipart upload when it is larger than `threshold`.
+///
+/// With a checksum algorithm, the upload fails unless S3 returns the checksum of the local file.
+pub async fn upload_object(
+    client: &aws_sdk_s3::Client,
+    upload: &ObjectUpload<'_>,
+    threshold: u64,
+    options: &MultipartOptions,
+    retry: &RetryOptions,
+) -> Result<UploadedObject> {
+    let path = upload.path.display();
+    let size = tokio::fs::metadata(upload.path)
+        .await
+        .map_err(|e| eyre!("Failed to open file {}: {}", path, e))?
+        .len();
+
+    let mut attributes = upload.attributes.clone();
+    if attributes.content_type.is_none() {
+        attributes.content_type = detect_content_type(
+            upload.path,
+            attributes.content_encoding.as_deref(),
+            upload.content_type_detection,
+        )
+        .await?;
+    }
+
+    if size > threshold.min(MAX_SINGLE_OPERATION_SIZE) {
+        let response = multipart::upload(
+            client,
+            upload.path,
+            size,
+            upload.bucket,
+            upload.key,
+            &attributes,
+            options,
+            retry,
+        )
+        .await?;
+        return Ok(UploadedObject {
+            e_tag: response.e_tag,
+            version_id: response.version_id,
+            size,
+        });
+    }
+
+    // Stream the file content
+    let body = ByteStream::from_path(upload.path)
+        .await
+        .map_err(|e| eyre!("Failed to read file {}: {}", path, e))?;
+    let (body, checksum) = match options.checksum_algorithm {
+        Some(algorithm) => {
+            let (body, checksum) = algorithm.body(body);
+            (body, Some(checksum))
+        }
+        None => (body, None),
+    };
+
+    options.throttle.request().await;
+    let response = client
+        .put_object()
+        .bucket(upload.bucket)
+        .key(upload.key)
+        .set_metadata(attributes.metadata)
+        .set_content_type(attributes.content_type)
+        .set_content_encoding(attributes.content_encoding)
+        .set_content_disposition(attributes.content_disposition)
+        .set_content_language(attributes.content_language)
+        .set_cache_control(attributes.cache_control)
+        .set_tagging(attributes.tagging)
+        .set_storage_class(attributes.storage_class)
+        .set_acl(attributes.acl)
+        .set_server_side_encryption(attributes.server_side_encryption)
+        .set_ssekms_key_id(attributes.sse_kms_key_id)
+        .set_bucket_key_enabled(attributes.bucket_key_enabled)
+        .set_checksum_algorithm(options.checksum_algorithm.map(ChecksumAlgorithm::sdk))
+        .body(options.throttle.body(body))
+        .send()
+        .await
+        .map_err(|e| classify(&f!("S3 PutObject failed for {}", path), e))?;
+
+    if let (Some(algorithm), Some(checksum)) = (options.checksum_algorithm, checksum) {
+        checksum::verify(
+            algorithm,
+            &path.to_string(),
+            &checksum::encode(&checksum.get()?),
+            algorithm.pick(
+                response.checksum_crc32.as_deref(),
+                response.checksum_crc32_c.as_deref(),
+                response.checksum_sha1.as_deref(),
+                response.checksum_sha256.as_deref(),
+            ),
+        )?;
+    }
+
+    Ok(UploadedObject {
+        e_tag: response.e_tag,
+        version_id: response.version_id,
+        size,
+    })
+}
+
+/// Works out the Content-Type of a local file. A file with a `content_encoding` is labeled
+/// after what it decompresses to, so `data.json.gz` with `gzip` is `application/json`.
+pub async fn detect_content_type(
+    path: &Path,
+    content_encoding: Option<&str>,
+    detection: ContentTypeDetection,
+) -> Result<Option<String>> {
+    if detection == ContentTypeDetection::Off {
+        return Ok(None);
+    }
+
+    let mut name = path;
+    if let (Some(encoding), Some(extension)) = (
+        content_encoding,
+        path.extension().and_then(|extension| extension.to_str()),
+    ) {
+        let compressed = match encoding.to_lowercase().as_str() {
+            "gzip" | "x-gzip" => &["gz", "gzip"][..],
+            "br" => &["br"],
+            "zstd" => &["zst", "zstd"],
+            "compress" => &["z"],
+            _ => &[],
+        };
+        if compressed.contains(&extension.to_lowercase().as_str()) {
+            name = Path::new(path.file_stem().unwrap_or_default());
+        }
+    }
+
+    if let Some(mime) = mime_guess::from_path(name).first() {
+        return Ok(Some(mime.essence_str().to_string()));
+    }
+
+    // The first bytes of a compressed file only tell the compression format.
+    if detection != ContentTypeDetection::Sniff || content_encoding.is_some() {
+        return Ok(None);
+    }
+
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut head = Vec::new();
+        std::fs::File::open(&path)
+            .and_then(|file| file.take(SNIFF_SIZE).read_to_end(&mut head))
+            .wrap_err_with(|| f!("Failed to read file {}", path.display()))?;
+
+        Ok(infer::get(&head).map(|kind| kind.mime_type().to_string()))
+    })
+    .await?
+}
+
+/// Key of a local file uploaded under `prefix`, named after the file.
+pub fn destination_key(prefix: &str, path: &Path) -> Result<String> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_eyre("cannot extract the file name of the local path")?;
+
+    if prefix.is_empty() || prefix.ends_with('/') {
+        Ok(f!("{}{}", prefix, file_name))
+    } else {
+        Ok(f!("{}/{}", prefix, file_name))
+    }
+}
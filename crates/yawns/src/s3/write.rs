@@ -0,0 +1,191 @@
+use crate::prelude::*;
+use aws_sdk_s3::types::{ObjectCannedAcl, ServerSideEncryption, StorageClass};
+use std::collections::HashMap;
+
+use super::copy::encode_tagging;
+use super::multipart::ObjectAttributes;
+use super::parse_key_val;
+
+/// Settings of the objects written by a command. Each of them can be overridden by the lines of
+/// a manifest.
+#[derive(Debug, Default, clap::Args, serde::Serialize, serde::Deserialize, Clone)]
+pub struct WriteOptions {
+    /// Storage class of the written objects, e.g. `STANDARD_IA` or `GLACIER_IR`.
+    #[clap(long, env = "AWS_S3_STORAGE_CLASS", value_parser = parse_storage_class)]
+    pub storage_class: Option<String>,
+    /// Server-side encryption of the written objects. Defaults to the bucket encryption.
+    #[clap(long, env = "AWS_S3_SSE", value_enum)]
+    pub sse: Option<Sse>,
+    /// KMS key used with `--sse aws:kms`, given as a key id, a key ARN, or an alias such as
+    /// `alias/my-key`, which is resolved to the ARN of its key. Implies `--sse aws:kms`.
+    #[clap(long, env = "AWS_S3_SSE_KMS_KEY_ID")]
+    pub sse_kms_key_id: Option<String>,
+    /// Use an S3 Bucket Key with `--sse aws:kms`, which reduces the number of KMS requests.
+    #[clap(long, env = "AWS_S3_BUCKET_KEY_ENABLED")]
+    pub bucket_key_enabled: bool,
+    /// Canned ACL of the written objects, e.g. `bucket-owner-full-control`.
+    #[clap(long, env = "AWS_S3_ACL", value_parser = parse_acl)]
+    pub acl: Option<String>,
+    /// Content-Type of the written objects. Uploaded files get one detected from their name
+    /// otherwise.
+    #[clap(long, env = "AWS_S3_CONTENT_TYPE")]
+    pub content_type: Option<String>,
+    /// Content-Encoding of the written objects, e.g. `gzip` for files compressed beforehand.
+    #[clap(long, env = "AWS_S3_CONTENT_ENCODING")]
+    pub content_encoding: Option<String>,
+    /// Cache-Control of the written objects, e.g. `max-age=3600`.
+    #[clap(long, env = "AWS_S3_CACHE_CONTROL")]
+    pub cache_control: Option<String>,
+    /// Tag to set on the written objects in the form of a KEY=VALUE pair. Can be given multiple
+    /// times. The tags of the source object are preserved by copies when no tag is given.
+    #[clap(long, value_parser = parse_key_val::<String, String>, number_of_values = 1)]
+    pub tagging: Vec<(String, String)>,
+}
+
+impl WriteOptions {
+    /// The attributes these options set, on top of the given metadata.
+    pub fn attributes(
+        &self,
+        metadata: Option<HashMap<String, String>>,
+    ) -> Result<ObjectAttributes> {
+        let mut attributes = ObjectAttributes {
+            metadata,
+            content_type: self.content_type.clone(),
+            content_encoding: self.content_encoding.clone(),
+            cache_control: self.cache_control.clone(),
+            tagging: (!self.tagging.is_empty()).then(|| {
+                encode_tagging(
+                    self.tagging
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.as_str())),
+                )
+            }),
+            storage_class: self.storage_class.as_deref().map(StorageClass::from),
+            acl: self.acl.as_deref().map(ObjectCannedAcl::from),
+            server_side_encryption: self.sse.map(Sse::sdk),
+            sse_kms_key_id: self.sse_kms_key_id.clone(),
+            bucket_key_enabled: self.bucket_key_enabled.then_some(true),
+            ..Default::default()
+        };
+        check_encryption(&mut attributes)?;
+
+        Ok(attributes)
+    }
+}
+
+/// Server-side encryption algorithms.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+pub enum Sse {
+    /// Encryption with a KMS key, the AWS managed one unless `--sse-kms-key-id` is given.
+    #[value(name = "aws:kms")]
+    #[serde(rename = "aws:kms")]
+    AwsKms,
+    /// Encryption with an S3 managed key.
+    #[value(name = "AES256")]
+    #[serde(rename = "AES256")]
+    Aes256,
+}
+
+impl Sse {
+    pub fn sdk(self) -> ServerSideEncryption {
+        match self {
+            Sse::AwsKms => ServerSideEncryption::AwsKms,
+            Sse::Aes256 => ServerSideEncryption::Aes256,
+        }
+    }
+
+    /// Parses an algorithm the way the `--sse` flag takes it.
+    pub fn parse(s: &str) -> Result<Self> {
+        <Sse as clap::ValueEnum>::from_str(s, true)
+            .map_err(|_| eyre!("unknown server-side encryption `{s}`, expected aws:kms or AES256"))
+    }
+}
+
+/// Checks that a storage class is one S3 knows about, returning it uppercase.
+pub fn parse_storage_class(s: &str) -> std::result::Result<String, String> {
+    let storage_class = s.to_uppercase();
+    if StorageClass::values().contains(&storage_class.as_str()) {
+        Ok(storage_class)
+    } else {
+        Err(f!("unknown storage class `{s}`"))
+    }
+}
+
+/// Checks that a canned ACL is one S3 knows about, returning it lowercase.
+pub fn parse_acl(s: &str) -> std::result::Result<String, String> {
+    let acl = s.to_lowercase();
+    if ObjectCannedAcl::values().contains(&acl.as_str()) {
+        Ok(acl)
+    } else {
+        Err(f!(
+            "unknown canned ACL `{s}`, expected one of {}",
+            ObjectCannedAcl::values().join(", ")
+        ))
+    }
+}
+
+/// Fills in the `aws:kms` encryption implied by a KMS key, and rejects the combinations S3
+/// would reject.
+pub fn check_encryption(attributes: &mut ObjectAttributes) -> Result<()> {
+    if attributes.sse_kms_key_id.is_some() && attributes.server_side_encryption.is_none() {
+        attributes.server_side_encryption = Some(ServerSideEncryption::AwsKms);
+    }
+
+    let kms = matches!(
+        attributes.server_side_encryption,
+        Some(ServerSideEncryption::AwsKms)
+    );
+    if attributes.sse_kms_key_id.is_some() && !kms {
+        return Err(eyre!("a KMS key can only be used with aws:kms encryption"));
+    }
+    if attributes.bucket_key_enabled.is_some() && !kms {
+        return Err(eyre!(
+            "a bucket key can only be used with aws:kms encryption"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Replaces the KMS aliases of the given attributes with the ARN of their key. Each alias is
+/// looked up once, before anything is written, so that a wrong alias fails the whole command.
+pub async fn resolve_kms_keys<'a>(
+    kms: &aws_sdk_kms::Client,
+    attributes: impl IntoIterator<Item = &'a mut ObjectAttributes>,
+) -> Result<()> {
+    let mut attributes: Vec<_> = attributes
+        .into_iter()
+        .filter(|attributes| {
+            attributes
+                .sse_kms_key_id
+                .as_deref()
+                .is_some_and(|key_id| key_id.starts_with("alias/"))
+        })
+        .collect();
+
+    let mut arns: HashMap<String, String> = HashMap::new();
+    for attributes in attributes.iter_mut() {
+        let Some(alias) = attributes.sse_kms_key_id.take() else {
+            continue;
+        };
+
+        let arn = match arns.get(&alias) {
+            Some(arn) => arn.clone(),
+            None => {
+                log::info!("Resolving KMS alias {}", alias);
+                let arn = crate::kms::describe_key(kms, &alias)
+                    .await
+                    .wrap_err_with(|| f!("Failed to resolve KMS alias {alias}"))?
+                    .and_then(|metadata| metadata.arn)
+                    .ok_or_else(|| eyre!("KMS alias {alias} has no key"))?;
+                arns.insert(alias, arn.clone());
+                arn
+            }
+        };
+        attributes.sse_kms_key_id = Some(arn);
+    }
+
+    Ok(())
+}